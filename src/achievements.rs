@@ -0,0 +1,203 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::game::{player_playing, CurrentRun};
+use crate::input::JumpEvent;
+use crate::player::{DeathEvent, ScoreEvent};
+use crate::stats::PlayerStats;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+const TOAST_DURATION_SECS: f32 = 2.5;
+const TOAST_FONT_SIZE: f32 = 18.0;
+const TOAST_TEXT_COLOR: Color = Color::srgb(0.1, 0., 0.);
+const TOAST_COLOR: Color = Color::srgba(1.0, 0.85, 0.3, 0.9);
+
+pub struct AchievementsPlugin;
+
+impl Plugin for AchievementsPlugin {
+    fn build(&self, app: &mut App) {
+        let definitions: Vec<Achievement> =
+            ron::from_str(include_str!("../assets/achievements.ron"))
+                .expect("assets/achievements.ron should be valid");
+
+        app.insert_resource(Achievements(definitions))
+            .init_resource::<ToastQueue>()
+            .add_systems(
+                PostUpdate,
+                check_achievements.run_if(
+                    on_event::<ScoreEvent>
+                        .or(on_event::<JumpEvent>)
+                        .or(on_event::<DeathEvent>)
+                        .and(player_playing),
+                ),
+            )
+            .add_systems(Update, show_toasts);
+    }
+}
+
+/// One entry from `assets/achievements.ron`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Achievement {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub requirement: Requirement,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub enum Requirement {
+    Score(usize),
+    FlapsInRun(u32),
+    DiedWithin(f32),
+    GamesPlayed(u32),
+    TotalPipes(u64),
+}
+
+/// What the requirements get checked against.
+struct Progress<'a> {
+    /// Highest score reached since the last check.
+    score: usize,
+    flaps: u32,
+    /// How long the run lasted, if it ended since the last check.
+    died_after_secs: Option<f32>,
+    stats: &'a PlayerStats,
+}
+
+impl Requirement {
+    fn is_met(&self, progress: &Progress) -> bool {
+        match *self {
+            Requirement::Score(score) => progress.score >= score,
+            Requirement::FlapsInRun(flaps) => progress.flaps >= flaps,
+            Requirement::DiedWithin(secs) => progress.died_after_secs.is_some_and(|t| t < secs),
+            Requirement::GamesPlayed(games) => progress.stats.games_played >= games,
+            Requirement::TotalPipes(pipes) => progress.stats.pipes_passed >= pipes,
+        }
+    }
+}
+
+#[derive(Resource, Deref)]
+pub struct Achievements(Vec<Achievement>);
+
+/// Ids of the unlocked achievements, mapped to the unix time they were unlocked at.
+#[derive(Resource, Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct UnlockedAchievements {
+    pub unlocked: BTreeMap<String, u64>,
+}
+
+/// Freshly unlocked achievements waiting for their toast.
+#[derive(Resource, Default)]
+struct ToastQueue(VecDeque<Achievement>);
+
+#[derive(Component)]
+struct Toast(Timer);
+
+// Runs after `Update` so stats and run bookkeeping already reflect this frame's events.
+// Anything unlocked here is saved along with the rest when the run ends.
+fn check_achievements(
+    achievements: Res<Achievements>,
+    mut unlocked: ResMut<UnlockedAchievements>,
+    mut toasts: ResMut<ToastQueue>,
+    mut score_events: EventReader<ScoreEvent>,
+    mut death_events: EventReader<DeathEvent>,
+    run: Res<CurrentRun>,
+    stats: Res<PlayerStats>,
+) {
+    let died = death_events.read().count() > 0;
+    let progress = Progress {
+        score: score_events
+            .read()
+            .map(|event| event.score)
+            .max()
+            .unwrap_or(0),
+        flaps: run.flaps,
+        died_after_secs: died.then(|| run.duration.elapsed_secs()),
+        stats: &stats,
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+
+    for achievement in achievements.iter() {
+        if unlocked.unlocked.contains_key(&achievement.id)
+            || !achievement.requirement.is_met(&progress)
+        {
+            continue;
+        }
+
+        info!("Achievement unlocked: {}", achievement.name);
+        unlocked.unlocked.insert(achievement.id.clone(), now);
+        toasts.0.push_back(achievement.clone());
+    }
+}
+
+fn show_toasts(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut toasts: ResMut<ToastQueue>,
+    mut toast_q: Query<(Entity, &mut Toast)>,
+    time: Res<Time>,
+) {
+    // Only one toast at a time, the rest wait their turn
+    if let Ok((entity, mut toast)) = toast_q.get_single_mut() {
+        if toast.0.tick(time.delta()).finished() {
+            commands.entity(entity).despawn_recursive();
+        }
+        return;
+    }
+
+    let Some(achievement) = toasts.0.pop_front() else {
+        return;
+    };
+
+    let font = asset_server.load("embedded://flappyboi/../assets/fonts/FiraSans-Bold.ttf");
+    commands
+        .spawn((
+            Toast(Timer::from_seconds(TOAST_DURATION_SECS, TimerMode::Once)),
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.),
+                bottom: Val::Px(10.),
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            // Toasts show up on top of every screen
+            GlobalZIndex(10),
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn((
+                    Node {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        padding: UiRect::axes(Val::Px(12.), Val::Px(6.)),
+                        ..default()
+                    },
+                    BackgroundColor(TOAST_COLOR),
+                    BorderRadius::all(Val::Px(6.)),
+                ))
+                .with_children(|toast| {
+                    toast.spawn((
+                        Text::new(format!("Achievement unlocked: {}", achievement.name)),
+                        TextColor(TOAST_TEXT_COLOR),
+                        TextFont {
+                            font: font.clone(),
+                            font_size: TOAST_FONT_SIZE,
+                            ..default()
+                        },
+                    ));
+                    toast.spawn((
+                        Text::new(achievement.description),
+                        TextColor(TOAST_TEXT_COLOR),
+                        TextFont {
+                            font: font.clone(),
+                            font_size: TOAST_FONT_SIZE * 0.8,
+                            ..default()
+                        },
+                    ));
+                });
+        });
+}
@@ -0,0 +1,478 @@
+use std::collections::HashSet;
+
+use super::{GameState, PlayState, Score};
+use crate::difficulty::{Difficulty, DifficultySettings};
+use crate::game::{CurrentRun, GameMode};
+use crate::input::JumpEvent;
+use crate::physics::{PhysicsSet, Position};
+use crate::pipes::{openings_ahead, Opening, Pipe};
+use crate::player::{Bird, Dead, Flight, BIRD_X, SCREEN_HEIGHT};
+use crate::settings::Settings;
+use crate::tuning::GameTuning;
+use bevy::ecs::system::SystemParam;
+use bevy::math::bounding::BoundingCircle;
+use bevy::prelude::*;
+
+// Hovers for half a second before taking off, so there's time to read "Get Ready"
+const TAKE_OFF_TICKS: u64 = 30;
+// Pipe pairs ahead that every flap has to leave a way through
+const LOOKAHEAD_PIPES: usize = 4;
+// A pipe pair counts as passed once it's this far behind the bird, like in `PlayerPlugin`
+const SCORE_DISTANCE: f32 = 30.;
+// Heights closer than this are the same height to the search, it tries each only once
+const HEIGHT_STEP: f32 = 0.125;
+// Leeway for `Search::out_of_reach`, bumping the ceiling can leave a bird a little higher than
+// falling the whole way would
+const REACH_SLACK: f32 = 1.;
+// How long the menu has to be left alone before the demo starts
+const IDLE_SECS: f32 = 10.;
+// How long the demo's death screen stays up before it flies again
+const RESTART_SECS: f32 = 2.;
+
+/// Flies the bird while `GameMode::Demo` is set. Needs nothing but the gameplay, so it also
+/// drives headless runs.
+pub struct AutopilotPlugin;
+
+impl Plugin for AutopilotPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Impassable>()
+            .init_resource::<Plan>()
+            .add_systems(OnEnter(PlayState::GetReady), start_over)
+            .add_systems(
+                FixedUpdate,
+                // Before anything reads flaps, like a replay's
+                (
+                    take_off.run_if(in_state(PlayState::GetReady)),
+                    steer.run_if(in_state(PlayState::Running)),
+                )
+                    .before(PhysicsSet::Move)
+                    .run_if(resource_equals(GameMode::Demo)),
+            );
+    }
+}
+
+/// Starts the autopilot demo once the menu has sat idle for a while, and ends it again on
+/// any input.
+pub struct AttractModePlugin;
+
+impl Plugin for AttractModePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(AutopilotPlugin)
+            .insert_resource(AttractTimer(Timer::from_seconds(
+                IDLE_SECS,
+                TimerMode::Once,
+            )))
+            .add_systems(OnEnter(GameState::Menu), wait_on_menu)
+            .add_systems(Update, start_demo.run_if(in_state(GameState::Menu)))
+            .add_systems(
+                OnEnter(GameState::DeathScreen),
+                wait_after_crash.run_if(resource_equals(GameMode::Demo)),
+            )
+            .add_systems(
+                Update,
+                (
+                    end_demo.run_if(in_state(GameState::Game).or(in_state(GameState::DeathScreen))),
+                    restart_demo.run_if(in_state(GameState::DeathScreen)),
+                )
+                    .chain()
+                    .run_if(resource_equals(GameMode::Demo)),
+            );
+    }
+}
+
+#[derive(Resource)]
+struct AttractTimer(Timer);
+
+/// The pipe pair, counted from 1, that the autopilot found no flaps at all to get through
+/// from where the bird was.
+#[derive(Resource, Default)]
+pub struct Impassable(pub Option<usize>);
+
+/// The flaps the autopilot found a way through with.
+#[derive(Resource, Default)]
+struct Plan {
+    /// Whether to flap on each tick to come, the next one last, with the height that leaves
+    /// the bird at.
+    flaps: Vec<(bool, f32)>,
+    /// Pipe pairs scored or still ahead of the bird when they were found.
+    pipes: usize,
+    /// Where the bird should be now, going by the flaps so far.
+    height: Option<f32>,
+}
+
+/// Whether anything at all was pressed this frame, bound to an action or not.
+#[derive(SystemParam)]
+struct AnyInput<'w, 's> {
+    keys: Res<'w, ButtonInput<KeyCode>>,
+    mouse: Res<'w, ButtonInput<MouseButton>>,
+    touches: Res<'w, Touches>,
+    gamepads: Query<'w, 's, &'static Gamepad>,
+}
+
+impl AnyInput<'_, '_> {
+    fn just_pressed(&self) -> bool {
+        self.keys.get_just_pressed().next().is_some()
+            || self.mouse.get_just_pressed().next().is_some()
+            || self.touches.any_just_pressed()
+            || self
+                .gamepads
+                .iter()
+                .any(|gamepad| gamepad.get_just_pressed().next().is_some())
+    }
+}
+
+fn take_off(
+    run: Res<CurrentRun>,
+    next_play_state: Res<NextState<PlayState>>,
+    mut jump_events: EventWriter<JumpEvent>,
+) {
+    // The bird stops counting ready ticks once the flap is in
+    if run.ready_ticks == TAKE_OFF_TICKS
+        && !matches!(*next_play_state, NextState::Pending(PlayState::Running))
+    {
+        jump_events.send_default();
+    }
+}
+
+// Tries out flapping and not flapping on every tick ahead, the same way the game steps the
+// bird, until it finds flaps that get through the next few pipe pairs, then flies those.
+fn steer(
+    settings: DifficultySettings,
+    tuning: Res<GameTuning>,
+    time: Res<Time>,
+    bird_q: Single<(&Bird, &Position, &Transform), Without<Dead>>,
+    pipes_q: Query<(&Pipe, &Position), Without<Bird>>,
+    (score, mut impassable, mut plan): (Res<Score>, ResMut<Impassable>, ResMut<Plan>),
+    mut jump_events: EventWriter<JumpEvent>,
+) {
+    let (bird, position, transform) = bird_q.into_inner();
+    let reach = tuning.pipes.width / 2. + tuning.bird.hitbox_radius();
+    let openings = openings_ahead(&pipes_q, reach);
+    let Some(current) = openings.first() else {
+        return;
+    };
+
+    // Already counted once the bird is past where it scores
+    let scored = |opening: &Opening| opening.x < BIRD_X - SCORE_DISTANCE;
+    let pipes = **score + openings.iter().filter(|opening| !scored(opening)).count();
+    // The search moves everything exactly like the game does, so its flaps keep working until a
+    // pipe pair it didn't know about shows up, or something else moves the bird
+    let on_plan = plan.pipes == pipes && plan.height == Some(position.current.y);
+    if !on_plan || plan.flaps.is_empty() {
+        let mut search = Search::new(
+            &settings,
+            &tuning,
+            time.delta_secs(),
+            **score,
+            openings.clone(),
+        );
+        let start = (bird.flight(), *position, *transform);
+        // Aiming further ahead keeps it out of spots it can't get out of again, but when
+        // there's no way through all of them the nearest one still has to be got through
+        let flaps = (1..=LOOKAHEAD_PIPES.min(openings.len()))
+            .rev()
+            .find_map(|pipes| search.flaps(start, pipes));
+        let Some(flaps) = flaps else {
+            plan.flaps.clear();
+            if impassable.0.is_none() {
+                let pipe = **score + usize::from(!scored(current));
+                warn!(
+                    "Impassable at pipe {}, no flaps get the bird through it",
+                    pipe
+                );
+                impassable.0 = Some(pipe);
+            }
+            return;
+        };
+        plan.flaps = flaps;
+        plan.pipes = pipes;
+    }
+
+    if let Some((flap, height)) = plan.flaps.pop() {
+        plan.height = Some(height);
+        if flap {
+            jump_events.send_default();
+        }
+    }
+}
+
+fn start_over(mut impassable: ResMut<Impassable>, mut plan: ResMut<Plan>) {
+    impassable.0 = None;
+    *plan = Plan::default();
+}
+
+/// A depth first search over flapping or not on every tick, for a way through the pipe pairs
+/// ahead of the bird.
+struct Search<'a> {
+    tuning: &'a GameTuning,
+    dt: f32,
+    /// Where the pipe pairs are on every tick ahead, they move the same whatever the bird does.
+    ticks: Vec<(Difficulty, Vec<Opening>)>,
+    settings: &'a DifficultySettings<'a>,
+    score: usize,
+    /// The first tick on which the bird is level with each pipe pair it has to get through.
+    level: Vec<(usize, Opening)>,
+    /// Ticks, speeds and heights already tried without finding a way through.
+    tried: HashSet<(usize, u32, i32)>,
+    /// The flaps of the way through, from the last tick back, as they're found.
+    path: Vec<(bool, f32)>,
+}
+
+type BirdState = (Flight, Position, Transform);
+
+impl<'a> Search<'a> {
+    fn new(
+        settings: &'a DifficultySettings<'a>,
+        tuning: &'a GameTuning,
+        dt: f32,
+        score: usize,
+        openings: Vec<Opening>,
+    ) -> Self {
+        Self {
+            tuning,
+            dt,
+            ticks: vec![(settings.at(score), openings)],
+            settings,
+            score,
+            level: Vec::new(),
+            tried: HashSet::new(),
+            path: Vec::new(),
+        }
+    }
+
+    /// Whether to flap on each tick to get through the first `pipes` pairs ahead, the next
+    /// tick last, with the height each leaves the bird at. `None` if no flaps get through them.
+    fn flaps(&mut self, start: BirdState, pipes: usize) -> Option<Vec<(bool, f32)>> {
+        let goal = self.tick_past(pipes)?;
+        let half_width = self.tuning.pipes.width / 2.;
+        self.level = (0..pipes)
+            .map(|pipe| {
+                let level = (0..goal)
+                    .find(|&tick| self.ticks[tick].1[pipe].x - half_width <= BIRD_X)
+                    .unwrap_or(goal);
+                (level, self.ticks[level].1[pipe])
+            })
+            .collect();
+        let next = self.next_opening(pipes, goal);
+        self.level.push(next);
+        self.tried.clear();
+        self.path.clear();
+        self.flaps_to_try(0, &start)
+            .into_iter()
+            .any(|flap| self.gets_through(0, start, flap, goal))
+            .then(|| std::mem::take(&mut self.path))
+    }
+
+    // The pair after the last one isn't known yet, but it's no further from it than `y_shift`.
+    // A bird that can still get to both the lowest and the highest it could be, by the time
+    // it's level with it, isn't left somewhere the next pair could trap it.
+    fn next_opening(&mut self, pipes: usize, goal: usize) -> (usize, Opening) {
+        let (difficulty, openings) = &self.ticks[goal];
+        let last = openings[pipes - 1];
+        let gap = difficulty.gap;
+        let (y_min, y_max) = difficulty.y_range;
+        let middle = ((last.bottom + last.top) / 2.).clamp(y_min, y_max);
+        let lowest = y_min.max(middle - difficulty.y_shift);
+        let highest = y_max.min(middle + difficulty.y_shift);
+        let opening = Opening {
+            x: last.x + gap,
+            bottom: highest - difficulty.opening / 2.,
+            top: lowest + difficulty.opening / 2.,
+        };
+
+        let half_width = self.tuning.pipes.width / 2.;
+        let mut tick = goal;
+        while self.ticks[tick].1[pipes - 1].x + gap - half_width > BIRD_X {
+            tick += 1;
+            if tick == self.ticks.len() {
+                self.move_pipes();
+            }
+        }
+        (tick, opening)
+    }
+
+    /// The first tick on which the bird is clear of the first `pipes` pairs.
+    fn tick_past(&mut self, pipes: usize) -> Option<usize> {
+        let reach = self.tuning.pipes.width / 2. + self.tuning.bird.hitbox_radius();
+        let last = self.ticks[0].1.get(pipes - 1)?.x;
+        // Every pair moves by the same amount, whatever its index
+        let mut tick = 0;
+        while self.ticks[tick].1[pipes - 1].x + reach >= BIRD_X {
+            tick += 1;
+            if tick == self.ticks.len() {
+                self.move_pipes();
+            }
+            // Pipes that stopped moving would never be got past
+            if tick > 1 && self.ticks[tick].1[pipes - 1].x >= last {
+                return None;
+            }
+        }
+        Some(tick)
+    }
+
+    // Scoring picks up a new difficulty on the next tick, same as in the game
+    fn move_pipes(&mut self) {
+        let (difficulty, mut moved) = self.ticks[self.ticks.len() - 1].clone();
+        for opening in moved.iter_mut() {
+            let was_scored = opening.x < BIRD_X - SCORE_DISTANCE;
+            opening.x -= difficulty.speed * self.dt;
+            if !was_scored && opening.x < BIRD_X - SCORE_DISTANCE {
+                self.score += 1;
+            }
+        }
+        self.ticks.push((self.settings.at(self.score), moved));
+    }
+
+    // Tries first what keeps it bouncing around the middle of the nearest opening it isn't
+    // clear of yet, which is usually a way through
+    fn flaps_to_try(&self, tick: usize, (_, position, _): &BirdState) -> [bool; 2] {
+        let (difficulty, openings) = &self.ticks[tick];
+        let reach = self.tuning.pipes.width / 2. + self.tuning.bird.hitbox_radius();
+        // How far above where it flapped a bird rises before falling again
+        let rise = difficulty.jump_speed.powi(2) / (2. * -difficulty.gravity);
+        let low = openings
+            .iter()
+            .find(|opening| opening.x + reach >= BIRD_X)
+            .is_some_and(|opening| position.current.y < (opening.bottom + opening.top - rise) / 2.);
+        [low, !low]
+    }
+
+    fn gets_through(
+        &mut self,
+        tick: usize,
+        (mut flight, mut position, mut transform): BirdState,
+        flap: bool,
+        goal: usize,
+    ) -> bool {
+        let difficulty = self.ticks[tick].0;
+        flight.step(
+            flap,
+            self.dt,
+            &difficulty,
+            &self.tuning.bird,
+            &mut position,
+            &mut transform,
+        );
+        let tick = tick + 1;
+        if self.crashes(tick, &position, &transform) {
+            return false;
+        }
+        let through = if self.out_of_reach(tick, flight, position.current.y, transform.scale.y) {
+            false
+        } else if tick == goal {
+            true
+        } else {
+            let height = (position.current.y / HEIGHT_STEP).round() as i32;
+            let state = (flight, position, transform);
+            self.tried.insert((tick, flight.speed().to_bits(), height))
+                && self
+                    .flaps_to_try(tick, &state)
+                    .into_iter()
+                    .any(|flap| self.gets_through(tick, state, flap, goal))
+        };
+        if through {
+            self.path.push((flap, position.current.y));
+        }
+        through
+    }
+
+    // Falling the whole way is the quickest a bird gets down and flapping every tick the
+    // quickest it gets up, so when neither would have it between the pipes of a pair by the time
+    // it's level with them, nothing in between does either
+    fn out_of_reach(&self, tick: usize, flight: Flight, y: f32, height: f32) -> bool {
+        let radius = self.tuning.bird.hitbox_radius();
+        self.level.iter().any(|&(level, opening)| {
+            if level <= tick {
+                return false;
+            }
+            let (mut lowest, mut highest) = ((y, flight.speed()), (y, flight.speed()));
+            for (difficulty, _) in &self.ticks[tick..level] {
+                lowest = self.drift(lowest, false, difficulty, height);
+                highest = self.drift(highest, true, difficulty, height);
+            }
+            lowest.0 > opening.top - radius + REACH_SLACK
+                || highest.0 < opening.bottom + radius - REACH_SLACK
+        })
+    }
+
+    // `Flight::step` without the turning, which doesn't change where the bird goes
+    fn drift(
+        &self,
+        (mut y, mut speed): (f32, f32),
+        flap: bool,
+        difficulty: &Difficulty,
+        height: f32,
+    ) -> (f32, f32) {
+        if flap {
+            speed = difficulty.jump_speed;
+        } else {
+            speed = (speed + difficulty.gravity * self.dt).max(self.tuning.bird.max_fall_speed);
+        }
+        y += speed * self.dt;
+        if y - height / 2. > SCREEN_HEIGHT {
+            speed = 0.;
+        }
+        (y.clamp(0., SCREEN_HEIGHT), speed)
+    }
+
+    // The same checks as `check_bounds` and `check_pipe_collision`
+    fn crashes(&self, tick: usize, position: &Position, transform: &Transform) -> bool {
+        if position.current.y - transform.scale.y / 2. <= 0. {
+            return true;
+        }
+        let hitbox = BoundingCircle::new(position.current, self.tuning.bird.hitbox_radius());
+        self.ticks[tick]
+            .1
+            .iter()
+            .any(|opening| opening.hits(hitbox, &self.tuning.pipes))
+    }
+}
+
+fn wait_on_menu(mut timer: ResMut<AttractTimer>) {
+    timer.0 = Timer::from_seconds(IDLE_SECS, TimerMode::Once);
+}
+
+fn wait_after_crash(mut timer: ResMut<AttractTimer>) {
+    timer.0 = Timer::from_seconds(RESTART_SECS, TimerMode::Once);
+}
+
+fn start_demo(
+    input: AnyInput,
+    settings: Res<Settings>,
+    time: Res<Time>,
+    mut timer: ResMut<AttractTimer>,
+    mut mode: ResMut<GameMode>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    if input.just_pressed() || !settings.attract_mode {
+        timer.0.reset();
+        return;
+    }
+
+    if timer.0.tick(time.delta()).just_finished() {
+        info!("Menu left idle, starting the autopilot demo");
+        *mode = GameMode::Demo;
+        game_state.set(GameState::Game);
+    }
+}
+
+fn end_demo(input: AnyInput, mut game_state: ResMut<NextState<GameState>>) {
+    if input.just_pressed() {
+        game_state.set(GameState::Menu);
+    }
+}
+
+// Flies again with a new seed, for as long as nobody touches anything
+fn restart_demo(
+    time: Res<Time>,
+    mut timer: ResMut<AttractTimer>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    // Ended by `end_demo` on this very frame
+    if matches!(*game_state, NextState::Pending(_)) {
+        return;
+    }
+    if timer.0.tick(time.delta()).just_finished() {
+        game_state.set(GameState::Game);
+    }
+}
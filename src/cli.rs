@@ -0,0 +1,71 @@
+use std::env;
+use std::path::PathBuf;
+
+use crate::difficulty::Preset;
+use bevy::prelude::*;
+
+/// Options from the command line, parsed once before the app starts.
+#[derive(Resource, Default, Clone, Debug)]
+pub struct CliArgs {
+    /// Play every run with this seed instead of a random one, `--seed <number>`.
+    pub seed: Option<u64>,
+    /// Watch this replay file once the menu shows up, `--replay <path>`.
+    pub replay: Option<PathBuf>,
+    /// Simulate a single run without a window or audio and print how it went, `--headless`.
+    pub headless: bool,
+    /// Let an agent play over stdin and stdout instead of showing a window, `--env`.
+    pub env: bool,
+    /// Ticks to flap on in a headless run, read from a file, `--inputs <path>`.
+    pub inputs: Option<PathBuf>,
+    /// Let the autopilot fly the headless run instead of following inputs, `--autopilot`.
+    pub autopilot: bool,
+    /// End a headless run once it reaches this score, `--until <score>`.
+    pub until: Option<usize>,
+    /// Preset for headless runs and the default one for `--env`, `--preset <name>`. The game
+    /// itself uses the one picked in the menu.
+    pub preset: Option<Preset>,
+}
+
+impl CliArgs {
+    pub fn parse() -> Self {
+        let mut parsed = CliArgs::default();
+        let mut args = env::args().skip(1);
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--seed" => match args.next().map(|value| value.parse()) {
+                    Some(Ok(seed)) => parsed.seed = Some(seed),
+                    Some(Err(e)) => error!("--seed needs a whole number: {}", e),
+                    None => error!("--seed needs a value"),
+                },
+                "--replay" => match args.next() {
+                    Some(path) => parsed.replay = Some(PathBuf::from(path)),
+                    None => error!("--replay needs a file"),
+                },
+                "--headless" => parsed.headless = true,
+                "--env" => parsed.env = true,
+                "--inputs" => match args.next() {
+                    Some(path) => parsed.inputs = Some(PathBuf::from(path)),
+                    None => error!("--inputs needs a file"),
+                },
+                "--autopilot" => parsed.autopilot = true,
+                "--until" => match args.next().map(|value| value.parse()) {
+                    Some(Ok(score)) => parsed.until = Some(score),
+                    Some(Err(e)) => error!("--until needs a whole number: {}", e),
+                    None => error!("--until needs a value"),
+                },
+                "--preset" => match args.next().map(|name| Preset::from_name(&name)) {
+                    Some(Some(preset)) => parsed.preset = Some(preset),
+                    Some(None) => error!(
+                        "--preset needs one of {}",
+                        Preset::ALL.map(Preset::name).join(", ")
+                    ),
+                    None => error!("--preset needs a value"),
+                },
+                _ => warn!("Ignoring unknown argument {}", arg),
+            }
+        }
+
+        parsed
+    }
+}
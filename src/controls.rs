@@ -0,0 +1,338 @@
+use super::{despawn_screen, GameState};
+use crate::input::{key_name, Action, ActionInput, Bindings, GamepadBindings};
+use crate::persistence::SaveRequested;
+use crate::settings::Settings;
+use bevy::prelude::*;
+
+const TITLE_FONT_SIZE: f32 = 30.0;
+const ROW_FONT_SIZE: f32 = 16.0;
+const TEXT_COLOR: Color = Color::srgb(0.1, 0., 0.);
+const PANEL_COLOR: Color = Color::srgba(1., 1., 1., 0.8);
+const SELECTED_ROW_COLOR: Color = Color::srgb(1.0, 0.7, 0.1);
+const LISTENING_ROW_COLOR: Color = Color::srgb(1.0, 0.45, 0.3);
+
+// The device row, one row per action, then the reset row
+const ROW_COUNT: usize = Action::ALL.len() + 2;
+
+pub struct ControlsPlugin;
+
+impl Plugin for ControlsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ControlsSelection>()
+            .add_event::<Rebind>()
+            .add_systems(OnEnter(GameState::Controls), controls_setup)
+            .add_systems(
+                OnExit(GameState::Controls),
+                (despawn_screen::<OnControlsScreen>, save_bindings),
+            )
+            .add_systems(
+                Update,
+                (
+                    controls_input,
+                    apply_rebinds.run_if(on_event::<Rebind>),
+                    update_controls_rows.run_if(
+                        resource_changed::<ControlsSelection>
+                            .or(resource_changed::<Settings>)
+                            .or(gamepads_changed),
+                    ),
+                )
+                    .chain()
+                    .run_if(in_state(GameState::Controls)),
+            );
+    }
+}
+
+#[derive(Resource, Default)]
+struct ControlsSelection {
+    index: usize,
+    /// Waiting for the key or button to bind to the selected action.
+    listening: bool,
+    /// The gamepad whose buttons are shown and changed, the keyboard if `None`.
+    device: Option<Entity>,
+}
+
+impl ControlsSelection {
+    fn action(&self) -> Option<Action> {
+        row_action(self.index)
+    }
+}
+
+/// A binding change picked on the screen. `ActionInput` borrows the settings, so they're
+/// changed by a separate system.
+#[derive(Event)]
+enum Rebind {
+    Key(Action, KeyCode),
+    Button(Entity, Action, GamepadButton),
+    /// Back to the default keys, or the default buttons of a gamepad.
+    Reset(Option<Entity>),
+}
+
+#[derive(Component)]
+struct OnControlsScreen;
+
+#[derive(Component)]
+struct ControlsRow(usize);
+
+#[derive(Component)]
+struct ControlsHint;
+
+fn controls_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut selection: ResMut<ControlsSelection>,
+) {
+    *selection = ControlsSelection::default();
+    let font = asset_server.load("embedded://flappyboi/../assets/fonts/FiraSans-Bold.ttf");
+    let row_font = TextFont {
+        font: font.clone(),
+        font_size: ROW_FONT_SIZE,
+        ..default()
+    };
+
+    commands
+        .spawn((
+            OnControlsScreen,
+            Node {
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                height: Val::Percent(100.),
+                width: Val::Percent(100.),
+                padding: UiRect::all(Val::Px(10.)),
+                row_gap: Val::Px(6.),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new("Controls"),
+                TextColor(TEXT_COLOR),
+                TextFont {
+                    font: font.clone(),
+                    font_size: TITLE_FONT_SIZE,
+                    ..default()
+                },
+            ));
+
+            parent
+                .spawn((
+                    Node {
+                        flex_direction: FlexDirection::Column,
+                        padding: UiRect::all(Val::Px(6.)),
+                        row_gap: Val::Px(2.),
+                        ..default()
+                    },
+                    BackgroundColor(PANEL_COLOR),
+                ))
+                .with_children(|table| {
+                    for index in 0..ROW_COUNT {
+                        table.spawn((
+                            ControlsRow(index),
+                            Text::default(),
+                            TextColor(TEXT_COLOR),
+                            row_font.clone(),
+                            Node {
+                                width: Val::Px(300.),
+                                padding: UiRect::axes(Val::Px(4.), Val::Px(2.)),
+                                ..default()
+                            },
+                            BackgroundColor(Color::NONE),
+                        ));
+                    }
+                });
+
+            parent.spawn((
+                ControlsHint,
+                Text::default(),
+                TextColor(TEXT_COLOR),
+                row_font.clone(),
+            ));
+        });
+}
+
+fn row_action(index: usize) -> Option<Action> {
+    index
+        .checked_sub(1)
+        .and_then(|index| Action::ALL.get(index).copied())
+}
+
+fn controls_input(
+    input: ActionInput,
+    keys: Res<ButtonInput<KeyCode>>,
+    gamepad_q: Query<(Entity, &Gamepad), With<GamepadBindings>>,
+    mut selection: ResMut<ControlsSelection>,
+    mut rebinds: EventWriter<Rebind>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    // An unplugged gamepad hands the screen back to the keyboard
+    if selection
+        .device
+        .is_some_and(|device| !gamepad_q.contains(device))
+    {
+        selection.device = None;
+        selection.listening = false;
+    }
+
+    if selection.listening {
+        let Some(action) = selection.action() else {
+            selection.listening = false;
+            return;
+        };
+        // Backing out is always possible, so the Back keys and buttons can't be bound here
+        if input.just_pressed(Action::Back) {
+            selection.listening = false;
+            return;
+        }
+        let rebind = match selection.device {
+            None => keys
+                .get_just_pressed()
+                .next()
+                .map(|&key| Rebind::Key(action, key)),
+            Some(device) => gamepad_q.get(device).ok().and_then(|(_, gamepad)| {
+                gamepad
+                    .get_just_pressed()
+                    .next()
+                    .map(|&button| Rebind::Button(device, action, button))
+            }),
+        };
+        if let Some(rebind) = rebind {
+            rebinds.send(rebind);
+            selection.listening = false;
+        }
+        return;
+    }
+
+    if input.just_pressed(Action::Down) {
+        selection.index = (selection.index + 1) % ROW_COUNT;
+    } else if input.just_pressed(Action::Up) {
+        selection.index = (selection.index + ROW_COUNT - 1) % ROW_COUNT;
+    } else if input.just_pressed(Action::Confirm) {
+        if selection.index == 0 {
+            // Keyboard first, then every gamepad in turn
+            let devices: Vec<Option<Entity>> = std::iter::once(None)
+                .chain(gamepad_q.iter().map(|(entity, _)| Some(entity)))
+                .collect();
+            let current = devices
+                .iter()
+                .position(|&device| device == selection.device)
+                .unwrap_or(0);
+            selection.device = devices[(current + 1) % devices.len()];
+        } else if selection.action().is_some() {
+            selection.listening = true;
+        } else {
+            rebinds.send(Rebind::Reset(selection.device));
+        }
+    } else if input.just_pressed(Action::Back) {
+        game_state.set(GameState::Menu);
+    }
+}
+
+// Gamepad changes go into the profile named after the controller, which every connected
+// controller of that model shares
+fn apply_rebinds(
+    mut rebinds: EventReader<Rebind>,
+    mut settings: ResMut<Settings>,
+    mut gamepad_q: Query<(&Name, &mut GamepadBindings)>,
+) {
+    for rebind in rebinds.read() {
+        let (gamepad, profile) = match *rebind {
+            Rebind::Key(action, key) => {
+                settings.bindings.toggle(action, key);
+                continue;
+            }
+            Rebind::Reset(None) => {
+                settings.bindings = Bindings::default();
+                continue;
+            }
+            Rebind::Button(gamepad, action, button) => {
+                let Ok((_, bindings)) = gamepad_q.get(gamepad) else {
+                    continue;
+                };
+                let mut profile = bindings.clone();
+                profile.toggle(action, button);
+                (gamepad, profile)
+            }
+            Rebind::Reset(Some(gamepad)) => (gamepad, GamepadBindings::default()),
+        };
+
+        let Ok((name, _)) = gamepad_q.get(gamepad) else {
+            continue;
+        };
+        let name = name.to_string();
+        for (other_name, mut bindings) in gamepad_q.iter_mut() {
+            if other_name.as_str() == name {
+                *bindings = profile.clone();
+            }
+        }
+        settings.gamepad_profiles.insert(name, profile);
+    }
+}
+
+fn gamepads_changed(
+    changed_q: Query<(), Changed<GamepadBindings>>,
+    mut removed: RemovedComponents<GamepadBindings>,
+) -> bool {
+    !changed_q.is_empty() || removed.read().count() > 0
+}
+
+fn update_controls_rows(
+    selection: Res<ControlsSelection>,
+    settings: Res<Settings>,
+    gamepad_q: Query<(&Name, &GamepadBindings)>,
+    mut row_q: Query<(&ControlsRow, &mut Text, &mut BackgroundColor), Without<ControlsHint>>,
+    mut hint_q: Query<&mut Text, With<ControlsHint>>,
+) {
+    let gamepad = selection
+        .device
+        .and_then(|device| gamepad_q.get(device).ok());
+
+    for (row, mut text, mut background) in row_q.iter_mut() {
+        let described = |action| match gamepad {
+            Some((_, bindings)) => bindings.describe(action),
+            None => settings.bindings.describe(action),
+        };
+        text.0 = match (row.0, row_action(row.0)) {
+            (0, _) => match gamepad {
+                Some((name, _)) => format!("Device: {}", name),
+                None => "Device: Keyboard".to_string(),
+            },
+            (_, Some(action)) => match described(action) {
+                bound if bound.is_empty() => format!("{}: none", action.name()),
+                bound => format!("{}: {}", action.name(), bound),
+            },
+            (_, None) => "Reset to defaults".to_string(),
+        };
+
+        background.0 = match (row.0 == selection.index, selection.listening) {
+            (true, true) => LISTENING_ROW_COLOR,
+            (true, false) => SELECTED_ROW_COLOR,
+            (false, _) => Color::NONE,
+        };
+    }
+
+    for mut hint in hint_q.iter_mut() {
+        hint.0 = if selection.listening {
+            format!(
+                "A new {} adds, a bound one removes, {} cancels",
+                if gamepad.is_some() { "button" } else { "key" },
+                first_key(&settings.bindings, Action::Back)
+            )
+        } else {
+            format!(
+                "{} to change, {} to go back",
+                first_key(&settings.bindings, Action::Confirm),
+                first_key(&settings.bindings, Action::Back)
+            )
+        };
+    }
+}
+
+fn first_key(bindings: &Bindings, action: Action) -> String {
+    bindings
+        .keys(action)
+        .first()
+        .map_or_else(|| action.name().to_string(), |&key| key_name(key))
+}
+
+fn save_bindings(mut save_requests: EventWriter<SaveRequested>) {
+    save_requests.send_default();
+}
@@ -0,0 +1,78 @@
+use std::collections::BTreeMap;
+
+use super::GameState;
+use crate::game::{start_run, CurrentRun, GameMode};
+use crate::leaderboard::unix_timestamp;
+use crate::persistence::SaveRequested;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+const SECS_PER_DAY: u64 = 86_400;
+
+pub struct DailyPlugin;
+
+impl Plugin for DailyPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            OnEnter(GameState::Game),
+            count_attempt
+                .after(start_run)
+                .run_if(resource_equals(GameMode::Daily)),
+        );
+    }
+}
+
+/// How the daily challenge went on one day.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct DailyResult {
+    pub best: usize,
+    pub attempts: u32,
+}
+
+/// Daily challenge results, by day since the unix epoch (UTC). Kept forever, so older days
+/// double as the history.
+#[derive(Resource, Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct DailyChallenge {
+    pub days: BTreeMap<u64, DailyResult>,
+}
+
+impl DailyChallenge {
+    pub fn today(&self) -> DailyResult {
+        self.on(today())
+    }
+
+    pub fn on(&self, day: u64) -> DailyResult {
+        self.days.get(&day).cloned().unwrap_or_default()
+    }
+
+    pub fn on_mut(&mut self, day: u64) -> &mut DailyResult {
+        self.days.entry(day).or_default()
+    }
+}
+
+/// Days since the unix epoch, the daily challenge changes at midnight UTC for everyone.
+pub fn today() -> u64 {
+    unix_timestamp() / SECS_PER_DAY
+}
+
+/// The seed everyone plays on the given day.
+pub fn seed_for_day(day: u64) -> u64 {
+    // Spread neighbouring days far apart, then keep it as short as the random seeds
+    day.wrapping_add(1)
+        .wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        .rotate_left(17)
+        % 1_000_000_000
+}
+
+fn count_attempt(
+    run: Res<CurrentRun>,
+    mut daily: ResMut<DailyChallenge>,
+    mut save_requests: EventWriter<SaveRequested>,
+) {
+    let Some(day) = run.day else {
+        return;
+    };
+    daily.on_mut(day).attempts += 1;
+    save_requests.send_default();
+}
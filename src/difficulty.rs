@@ -0,0 +1,254 @@
+use super::Score;
+use crate::game::GameMode;
+use crate::physics::PhysicsSet;
+use crate::player::SCREEN_HEIGHT;
+use crate::tuning::GameTuning;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub struct DifficultyPlugin;
+
+impl Plugin for DifficultyPlugin {
+    fn build(&self, app: &mut App) {
+        let tuning = GameTuning::built_in();
+        app.insert_resource(difficulty_at(&tuning, Preset::default(), 0))
+            // Picked up on the tick after the score changes, same as the pipes see it
+            .add_systems(
+                FixedUpdate,
+                update_difficulty.before(PhysicsSet::Move).run_if(
+                    resource_changed::<Score>
+                        .or(resource_changed::<Preset>)
+                        .or(resource_changed::<GameTuning>)
+                        .or(resource_changed::<GameMode>),
+                ),
+            );
+    }
+}
+
+/// The difficulty picked in the menu. Every preset keeps its own highscores.
+#[derive(
+    Resource,
+    Serialize,
+    Deserialize,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Debug,
+)]
+pub enum Preset {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+    Insane,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 4] = [Preset::Easy, Preset::Normal, Preset::Hard, Preset::Insane];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::Easy => "Easy",
+            Preset::Normal => "Normal",
+            Preset::Hard => "Hard",
+            Preset::Insane => "Insane",
+        }
+    }
+
+    /// Looks a preset up by its name, ignoring case.
+    pub fn from_name(name: &str) -> Option<Preset> {
+        Preset::ALL
+            .into_iter()
+            .find(|preset| preset.name().eq_ignore_ascii_case(name))
+    }
+
+    /// The next harder preset, wrapping around to the easiest.
+    pub fn next(self) -> Preset {
+        let index = Preset::ALL.iter().position(|&preset| preset == self);
+        Preset::ALL[index.map_or(0, |index| (index + 1) % Preset::ALL.len())]
+    }
+}
+
+/// How hard the game is right now, for the selected preset at the current score.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct Difficulty {
+    /// Pipe scroll speed in pixels per second, the background follows it.
+    pub speed: f32,
+    /// Height of the hole between a pipe pair.
+    pub opening: f32,
+    /// Horizontal distance between two pipe pairs.
+    pub gap: f32,
+    pub gravity: f32,
+    pub jump_speed: f32,
+    /// Lowest and highest height for the middle of a pipe opening.
+    pub y_range: (f32, f32),
+    /// Furthest the middle of an opening can be above or below the one before it.
+    pub y_shift: f32,
+}
+
+/// What one preset changes, from the `presets` in `assets/game.tuning.ron`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct PresetTuning {
+    gravity: f32,
+    jump_speed: f32,
+    speed_scale: f32,
+    opening_scale: f32,
+    y_range: (f32, f32),
+    y_shift: f32,
+}
+
+impl PresetTuning {
+    /// Catches values that would break the game, like a pipe range with nothing in it.
+    pub fn validate(&self) -> Result<(), String> {
+        let (y_min, y_max) = self.y_range;
+        // Also false for NaN
+        if !(0. <= y_min && y_min < y_max && y_max <= SCREEN_HEIGHT) {
+            return Err(format!(
+                "y_range ({}, {}) should go from low to high, between 0 and {}",
+                y_min, y_max, SCREEN_HEIGHT
+            ));
+        }
+        positive("y_shift", self.y_shift)?;
+        positive("speed_scale", self.speed_scale)?;
+        positive("opening_scale", self.opening_scale)
+    }
+}
+
+impl Default for PresetTuning {
+    fn default() -> Self {
+        Self {
+            gravity: -600.,
+            jump_speed: 300.,
+            speed_scale: 1.,
+            opening_scale: 1.,
+            y_range: (70., 300.),
+            y_shift: 120.,
+        }
+    }
+}
+
+/// One point of the `curve` in `assets/game.tuning.ron`.
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct CurvePoint {
+    score: usize,
+    speed: f32,
+    opening: f32,
+    gap: f32,
+}
+
+/// Catches curves that would stop the pipes or close their openings, and scores out of order,
+/// which `sample_curve` can't blend between.
+pub fn validate_curve(points: &[CurvePoint]) -> Result<(), String> {
+    if points.is_empty() {
+        return Err("the curve should have at least one point".to_string());
+    }
+    for point in points {
+        positive("speed", point.speed)
+            .and_then(|()| positive("opening", point.opening))
+            .and_then(|()| positive("gap", point.gap))
+            .map_err(|e| format!("curve point at score {}: {}", point.score, e))?;
+    }
+    if let Some(pair) = points
+        .windows(2)
+        .find(|pair| pair[0].score >= pair[1].score)
+    {
+        return Err(format!(
+            "curve scores should go up, {} comes after {}",
+            pair[1].score, pair[0].score
+        ));
+    }
+    Ok(())
+}
+
+// Also an error for NaN
+fn positive(name: &str, value: f32) -> Result<(), String> {
+    if value.is_nan() || value <= 0. {
+        return Err(format!("{} {} should be above 0", name, value));
+    }
+    Ok(())
+}
+
+fn sample_curve(points: &[CurvePoint], score: usize) -> CurvePoint {
+    let Some(&first) = points.first() else {
+        error!("The difficulty curve has no points");
+        return CurvePoint {
+            score,
+            speed: 150.,
+            opening: 120.,
+            gap: 250.,
+        };
+    };
+
+    let next = points.iter().position(|point| point.score > score);
+    let (from, to) = match next {
+        Some(0) => return first,
+        Some(index) => (points[index - 1], points[index]),
+        None => return points[points.len() - 1],
+    };
+
+    let t = (score - from.score) as f32 / (to.score - from.score) as f32;
+    CurvePoint {
+        score,
+        speed: from.speed.lerp(to.speed, t),
+        opening: from.opening.lerp(to.opening, t),
+        gap: from.gap.lerp(to.gap, t),
+    }
+}
+
+pub fn difficulty_at(tuning: &GameTuning, preset: Preset, score: usize) -> Difficulty {
+    let curve = sample_curve(&tuning.curve, score);
+    let preset_tuning = tuning.presets.get(&preset).cloned().unwrap_or_else(|| {
+        error!("The tuning has no {} preset", preset.name());
+        PresetTuning::default()
+    });
+
+    Difficulty {
+        speed: curve.speed * preset_tuning.speed_scale,
+        opening: curve.opening * preset_tuning.opening_scale,
+        gap: curve.gap,
+        gravity: preset_tuning.gravity,
+        jump_speed: preset_tuning.jump_speed,
+        y_range: preset_tuning.y_range,
+        y_shift: preset_tuning.y_shift,
+    }
+}
+
+/// Everything needed to work out the difficulty at any score.
+#[derive(SystemParam)]
+pub struct DifficultySettings<'w> {
+    tuning: Res<'w, GameTuning>,
+    preset: Res<'w, Preset>,
+    mode: Res<'w, GameMode>,
+}
+
+impl DifficultySettings<'_> {
+    /// The preset the current run is played on.
+    pub fn preset(&self) -> Preset {
+        match *self.mode {
+            GameMode::Classic | GameMode::Demo | GameMode::Evolution | GameMode::Versus => {
+                *self.preset
+            }
+            // The daily challenge is the same for everyone, whatever they picked
+            GameMode::Daily => Preset::Normal,
+            GameMode::Replay(preset) | GameMode::Race { preset, .. } => preset,
+        }
+    }
+
+    pub fn at(&self, score: usize) -> Difficulty {
+        difficulty_at(&self.tuning, self.preset(), score)
+    }
+}
+
+fn update_difficulty(
+    score: Res<Score>,
+    settings: DifficultySettings,
+    mut difficulty: ResMut<Difficulty>,
+) {
+    *difficulty = settings.at(**score);
+}
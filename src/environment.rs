@@ -0,0 +1,251 @@
+use std::io::{self, BufRead, Write};
+
+use super::{GameState, PlayState, Score};
+use crate::cli::CliArgs;
+use crate::difficulty::Preset;
+use crate::game::{CurrentRun, GameMode};
+use crate::headless;
+use crate::input::JumpEvent;
+use crate::physics::Position;
+use crate::pipes::{openings_ahead, Pipe};
+use crate::player::{Bird, Dead, BIRD_X};
+use crate::replay::{Replay, ReplayPlayback};
+use crate::tuning::GameTuning;
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+// Worth about as many ticks as it takes to get from one pipe to the next
+const PIPE_REWARD: f32 = 100.;
+
+/// A line read from stdin.
+#[derive(Deserialize)]
+#[serde(tag = "cmd", rename_all = "lowercase")]
+enum Request {
+    /// Starts a new run, already in the air after its first flap.
+    Reset {
+        seed: Option<u64>,
+        preset: Option<Preset>,
+    },
+    /// Plays one fixed tick, flapping at the start of it or not.
+    Step { flap: bool },
+}
+
+/// What the agent gets to see, in pixels and pixels per second.
+#[derive(Serialize, Default)]
+struct Observation {
+    bird_y: f32,
+    /// Vertical speed, positive while rising.
+    bird_speed: f32,
+    /// How far ahead of the bird the middle of the next opening is.
+    gap_x: f32,
+    /// Height of the middle of the next opening.
+    gap_y: f32,
+}
+
+/// A line written to stdout for every request.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Response {
+    State {
+        observation: Observation,
+        /// 1 for every tick survived, plus `PIPE_REWARD` for every pipe passed.
+        reward: f32,
+        done: bool,
+        score: usize,
+        seed: u64,
+        tick: u64,
+    },
+    Error {
+        error: String,
+    },
+}
+
+/// Lets an agent play the game over stdin and stdout, one JSON object per line. Send
+/// `{"cmd": "reset", "seed": 5}` to start a run, then `{"cmd": "step", "flap": true}` for
+/// every tick. A seed always plays out the same way for the same flaps.
+pub fn run() -> AppExit {
+    let mut app = headless::simulation();
+    // Parsed after the log plugin is in, so mistakes in the arguments get reported
+    let args = CliArgs::parse();
+    let default_preset = args.preset.unwrap_or(Preset::Normal);
+    app.insert_resource(default_preset)
+        .insert_resource(GameMode::Replay(default_preset))
+        .insert_resource(args);
+    app.finish();
+    app.cleanup();
+
+    let mut started = false;
+    let mut done = false;
+    let mut stdout = io::stdout().lock();
+    for line in io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                error!("Failed to read from stdin: {}", e);
+                return AppExit::error();
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str(&line) {
+            Ok(Request::Reset { seed, preset }) => {
+                reset(&mut app, seed, preset.unwrap_or(default_preset));
+                started = true;
+                done = false;
+                respond(app.world_mut(), 0., done)
+            }
+            Ok(Request::Step { .. }) if !started => Response::Error {
+                error: "reset before stepping".to_string(),
+            },
+            // A finished run stays where it ended until the next reset
+            Ok(Request::Step { .. }) if done => respond(app.world_mut(), 0., done),
+            Ok(Request::Step { flap }) => {
+                let score = **app.world().resource::<Score>();
+                if flap {
+                    app.world_mut().send_event_default::<JumpEvent>();
+                }
+                app.update();
+
+                done = !bird_alive(app.world_mut());
+                let passed = **app.world().resource::<Score>() - score;
+                let reward = if done { 0. } else { 1. } + passed as f32 * PIPE_REWARD;
+                respond(app.world_mut(), reward, done)
+            }
+            Err(e) => Response::Error {
+                error: e.to_string(),
+            },
+        };
+
+        let written = serde_json::to_writer(&mut stdout, &response)
+            .map_err(io::Error::from)
+            .and_then(|()| writeln!(stdout))
+            .and_then(|()| stdout.flush());
+        if let Err(e) = written {
+            error!("Failed to write to stdout: {}", e);
+            return AppExit::error();
+        }
+    }
+
+    AppExit::Success
+}
+
+// The run is played as a replay without flaps, which takes care of the first flap and keeps
+// it off the highscores. Every other flap comes from the agent.
+fn reset(app: &mut App, seed: Option<u64>, preset: Preset) {
+    let seed = seed.unwrap_or_else(|| rand::thread_rng().gen_range(0..1_000_000_000));
+    let replay = Replay {
+        seed,
+        preset,
+        tuning_version: app.world().resource::<GameTuning>().version,
+        ready_ticks: 0,
+        jumps: Vec::new(),
+        ticks: 0,
+        score: 0,
+        recorded_at: 0,
+    };
+    app.insert_resource(preset)
+        .insert_resource(GameMode::Replay(preset))
+        .insert_resource(ReplayPlayback::new(replay));
+
+    // Through the menu, so leaving and entering the game tear down and set up the run
+    if *app.world().resource::<State<GameState>>() != GameState::Menu {
+        set_game_state(app, GameState::Menu);
+        app.update();
+    }
+    set_game_state(app, GameState::Game);
+    while app
+        .world()
+        .get_resource::<State<PlayState>>()
+        .is_none_or(|play_state| *play_state != PlayState::Running)
+    {
+        app.update();
+    }
+}
+
+fn set_game_state(app: &mut App, state: GameState) {
+    app.world_mut()
+        .resource_mut::<NextState<GameState>>()
+        .set(state);
+}
+
+fn bird_alive(world: &mut World) -> bool {
+    world
+        .query_filtered::<(), (With<Bird>, Without<Dead>)>()
+        .iter(world)
+        .next()
+        .is_some()
+}
+
+fn respond(world: &mut World, reward: f32, done: bool) -> Response {
+    let tuning = world.resource::<GameTuning>();
+    let reach = tuning.pipes.width / 2. + tuning.bird.hitbox_radius();
+
+    let mut observation = Observation::default();
+    if let Some((bird, position)) = world.query::<(&Bird, &Position)>().iter(world).next() {
+        observation.bird_y = position.current.y;
+        observation.bird_speed = bird.speed();
+    }
+    let openings = openings_ahead(world.query::<(&Pipe, &Position)>().iter(world), reach);
+    if let Some(opening) = openings.first() {
+        observation.gap_x = opening.x - BIRD_X;
+        observation.gap_y = (opening.bottom + opening.top) / 2.;
+    }
+
+    let run = world.resource::<CurrentRun>();
+    Response::State {
+        observation,
+        reward,
+        done,
+        score: **world.resource::<Score>(),
+        seed: run.seed.unwrap_or_default(),
+        tick: run.ticks,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEPS: usize = 300;
+    // Every so often, so the bird climbs and falls over the run
+    const FLAP_EVERY: usize = 25;
+
+    fn environment() -> App {
+        let mut app = headless::simulation();
+        app.insert_resource(Preset::Normal)
+            .insert_resource(GameMode::Replay(Preset::Normal))
+            .insert_resource(CliArgs::default());
+        app.finish();
+        app.cleanup();
+        app
+    }
+
+    // The responses `run` would write for a reset and the steps after it
+    fn responses(app: &mut App, seed: u64) -> Vec<String> {
+        reset(app, Some(seed), Preset::Normal);
+        let mut lines = vec![serde_json::to_string(&respond(app.world_mut(), 0., false)).unwrap()];
+        for step in 0..STEPS {
+            if step % FLAP_EVERY == 0 {
+                app.world_mut().send_event_default::<JumpEvent>();
+            }
+            app.update();
+            let done = !bird_alive(app.world_mut());
+            lines.push(serde_json::to_string(&respond(app.world_mut(), 0., done)).unwrap());
+            if done {
+                break;
+            }
+        }
+        lines
+    }
+
+    #[test]
+    fn resetting_with_one_seed_plays_out_the_same() {
+        let mut app = environment();
+        let first = responses(&mut app, 3);
+        let second = responses(&mut app, 3);
+        assert_eq!(first, second);
+    }
+}
@@ -0,0 +1,292 @@
+use std::cmp::Reverse;
+
+use super::{despawn_screen, GameState, PlayState, Score};
+use crate::difficulty::Difficulty;
+use crate::game::{restart_run, GameMode};
+use crate::input::JumpEvent;
+use crate::physics::{PhysicsSet, Position};
+use crate::pipes::{openings_ahead, Pipe};
+use crate::player::{bird_alive, Bird, Dead, BIRD_X, SCREEN_HEIGHT};
+use crate::tuning::GameTuning;
+use bevy::prelude::*;
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+const POPULATION: usize = 300;
+// The best birds of a generation fly again unchanged
+const ELITES: usize = 10;
+// Every other bird of the next generation is bred from two of these
+const PARENTS: usize = 50;
+// Chance for every weight of a newly bred network to get nudged, and how far
+const MUTATION_RATE: f64 = 0.1;
+const MUTATION_SIZE: f32 = 0.5;
+
+const INPUTS: usize = 4;
+const HIDDEN: usize = 6;
+
+const BIRD_COLOR: Color = Color::srgba(1., 1., 1., 0.35);
+const ELITE_COLOR: Color = Color::srgba(1.0, 0.7, 0.1, 0.9);
+const HUD_FONT_SIZE: f32 = 18.0;
+const HUD_COLOR: Color = Color::srgb(0.1, 0., 0.);
+
+/// Fills the sky with birds flown by small neural networks, and breeds the next generation
+/// from the ones that got furthest once they're all down.
+pub struct EvolutionPlugin;
+
+impl Plugin for EvolutionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            OnEnter(GameState::Game),
+            (init_population, spawn_population, hud_setup)
+                .chain()
+                .run_if(resource_equals(GameMode::Evolution)),
+        )
+        .add_systems(OnExit(GameState::Game), despawn_screen::<OnEvolutionHud>)
+        // Every visit from the menu starts over from random networks
+        .add_systems(OnEnter(GameState::Menu), forget_population)
+        .add_systems(
+            FixedUpdate,
+            // Before anything reads flaps, like the player's
+            (
+                take_off.run_if(in_state(PlayState::GetReady)),
+                think.run_if(in_state(PlayState::Running)),
+            )
+                .before(PhysicsSet::Move)
+                .run_if(resource_equals(GameMode::Evolution)),
+        )
+        .add_systems(
+            Update,
+            (
+                hide_crashed,
+                update_hud,
+                next_generation.run_if(in_state(PlayState::Running).and(not(bird_alive))),
+            )
+                .run_if(in_state(GameState::Game).and(resource_equals(GameMode::Evolution))),
+        );
+    }
+}
+
+/// A tiny fully connected network: the inputs, one hidden layer and a single output that
+/// flaps when it's above zero.
+#[derive(Clone, Debug)]
+struct Network {
+    /// For every hidden neuron its input weights and bias, then the output's.
+    weights: Vec<f32>,
+}
+
+impl Network {
+    const WEIGHTS: usize = (INPUTS + 1) * HIDDEN + HIDDEN + 1;
+
+    fn random(rng: &mut impl Rng) -> Self {
+        Self {
+            weights: (0..Self::WEIGHTS)
+                .map(|_| rng.gen_range(-1.0..=1.0))
+                .collect(),
+        }
+    }
+
+    fn output(&self, inputs: [f32; INPUTS]) -> f32 {
+        let (hidden_weights, output_weights) = self.weights.split_at((INPUTS + 1) * HIDDEN);
+        let hidden = hidden_weights.chunks(INPUTS + 1).map(|weights| {
+            let sum: f32 = inputs
+                .iter()
+                .zip(weights)
+                .map(|(input, weight)| input * weight)
+                .sum();
+            (sum + weights[INPUTS]).tanh()
+        });
+        let sum: f32 = hidden
+            .zip(output_weights)
+            .map(|(hidden, weight)| hidden * weight)
+            .sum();
+        sum + output_weights[HIDDEN]
+    }
+
+    /// Every weight from either parent, then a few of them nudged.
+    fn bred_with(&self, other: &Network, rng: &mut impl Rng) -> Self {
+        let weights = self
+            .weights
+            .iter()
+            .zip(&other.weights)
+            .map(|(&a, &b)| {
+                let weight = if rng.gen_bool(0.5) { a } else { b };
+                if rng.gen_bool(MUTATION_RATE) {
+                    weight + rng.gen_range(-MUTATION_SIZE..=MUTATION_SIZE)
+                } else {
+                    weight
+                }
+            })
+            .collect();
+        Self { weights }
+    }
+}
+
+/// The networks for the generation being flown.
+#[derive(Resource)]
+struct Population {
+    generation: u32,
+    networks: Vec<Network>,
+    /// Highest score of any generation so far.
+    best_score: usize,
+}
+
+/// A bird flown by a network instead of the player.
+#[derive(Component)]
+struct Brain {
+    network: Network,
+    /// How long it stayed in the air, what breeding picks the parents by.
+    ticks_alive: u64,
+}
+
+#[derive(Component)]
+struct OnEvolutionHud;
+
+fn init_population(mut commands: Commands, population: Option<Res<Population>>) {
+    if population.is_some() {
+        return;
+    }
+    let mut rng = rand::thread_rng();
+    commands.insert_resource(Population {
+        generation: 1,
+        networks: (0..POPULATION).map(|_| Network::random(&mut rng)).collect(),
+        best_score: 0,
+    });
+}
+
+fn forget_population(mut commands: Commands) {
+    commands.remove_resource::<Population>();
+}
+
+fn spawn_population(mut commands: Commands, population: Res<Population>) {
+    for (index, network) in population.networks.iter().enumerate() {
+        // The elites come first, from the second generation on they stand out
+        let color = if population.generation > 1 && index < ELITES {
+            ELITE_COLOR
+        } else {
+            BIRD_COLOR
+        };
+        commands.spawn((
+            Bird::default(),
+            Brain {
+                network: network.clone(),
+                ticks_alive: 0,
+            },
+            Sprite { color, ..default() },
+            Position::new(Vec2::new(BIRD_X, SCREEN_HEIGHT / 2.)),
+            Transform::from_xyz(BIRD_X, SCREEN_HEIGHT / 2., 5.),
+        ));
+    }
+}
+
+fn hud_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((
+        OnEvolutionHud,
+        Text::default(),
+        TextColor(HUD_COLOR),
+        TextFont {
+            font: asset_server.load("embedded://flappyboi/../assets/fonts/FiraSans-Bold.ttf"),
+            font_size: HUD_FONT_SIZE,
+            ..default()
+        },
+        TextLayout::new_with_justify(JustifyText::Right),
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(5.),
+            right: Val::Px(5.),
+            ..default()
+        },
+    ));
+}
+
+// Nobody has to flap to start a generation
+fn take_off(mut jump_events: EventWriter<JumpEvent>) {
+    jump_events.send_default();
+}
+
+// The inputs are scaled to roughly -1..1, so no input drowns out the others
+fn think(
+    difficulty: Res<Difficulty>,
+    tuning: Res<GameTuning>,
+    pipes_q: Query<(&Pipe, &Position)>,
+    mut bird_q: Query<(&mut Bird, &mut Brain, &Position), Without<Dead>>,
+) {
+    let reach = tuning.pipes.width / 2. + tuning.bird.hitbox_radius();
+    let Some(opening) = openings_ahead(&pipes_q, reach).first().copied() else {
+        return;
+    };
+    let gap_y = (opening.bottom + opening.top) / 2.;
+
+    for (mut bird, mut brain, position) in bird_q.iter_mut() {
+        brain.ticks_alive += 1;
+        let inputs = [
+            position.current.y / SCREEN_HEIGHT,
+            bird.speed() / difficulty.jump_speed,
+            // The window is as wide as it is high
+            (opening.x - BIRD_X) / SCREEN_HEIGHT,
+            (gap_y - position.current.y) / SCREEN_HEIGHT,
+        ];
+        if brain.network.output(inputs) > 0. {
+            bird.flap();
+        }
+    }
+}
+
+fn hide_crashed(mut bird_q: Query<&mut Visibility, (With<Brain>, Added<Dead>)>) {
+    for mut visibility in bird_q.iter_mut() {
+        *visibility = Visibility::Hidden;
+    }
+}
+
+fn update_hud(
+    population: Res<Population>,
+    brain_q: Query<(), (With<Brain>, Without<Dead>)>,
+    mut hud_q: Query<&mut Text, With<OnEvolutionHud>>,
+) {
+    for mut text in hud_q.iter_mut() {
+        text.0 = format!(
+            "Generation {}\nAlive {}/{}\nBest score {}",
+            population.generation,
+            brain_q.iter().count(),
+            population.networks.len(),
+            population.best_score,
+        );
+    }
+}
+
+fn next_generation(
+    mut commands: Commands,
+    mut population: ResMut<Population>,
+    score: Res<Score>,
+    brain_q: Query<&Brain>,
+    mut next_play_state: ResMut<NextState<PlayState>>,
+) {
+    let mut ranked: Vec<&Brain> = brain_q.iter().collect();
+    ranked.sort_by_key(|brain| Reverse(brain.ticks_alive));
+    info!(
+        "Generation {} scored {}, its best bird flew for {} ticks",
+        population.generation,
+        **score,
+        ranked.first().map_or(0, |brain| brain.ticks_alive)
+    );
+
+    let mut rng = rand::thread_rng();
+    let parents = &ranked[..ranked.len().min(PARENTS)];
+    let mut networks: Vec<Network> = ranked
+        .iter()
+        .take(ELITES)
+        .map(|brain| brain.network.clone())
+        .collect();
+    while networks.len() < POPULATION {
+        let (Some(a), Some(b)) = (parents.choose(&mut rng), parents.choose(&mut rng)) else {
+            break;
+        };
+        networks.push(a.network.bred_with(&b.network, &mut rng));
+    }
+
+    population.generation += 1;
+    population.networks = networks;
+    population.best_score = population.best_score.max(**score);
+
+    next_play_state.set(PlayState::GetReady);
+    commands.queue(restart_run);
+}
@@ -1,84 +1,204 @@
-use super::GameState;
+use super::{GameState, Highscores, PlayState, Score};
+use crate::cli::CliArgs;
+use crate::daily::{self, DailyChallenge};
+use crate::difficulty::Preset;
+use crate::input::JumpEvent;
+use crate::physics::PhysicsSet;
+use crate::player::ScoreEvent;
+use crate::replay::ReplayPlayback;
 use crate::{pipes, player};
-use bevy::{asset::embedded_asset, prelude::*};
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::time::Stopwatch;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
 pub struct GamePlugin;
 
 impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
-        embedded_asset!(app, "../assets/audio/flop.ogg");
-        embedded_asset!(app, "../assets/audio/death.ogg");
-        embedded_asset!(app, "../assets/audio/woho.ogg");
-        embedded_asset!(app, "../assets/audio/themesong.ogg");
-        embedded_asset!(app, "../assets/bg.png");
-
-        app.add_systems(Startup, game_setup)
-            .add_systems(Update, move_background.run_if(in_state(GameState::Game)))
+        app.insert_resource(Score(0))
+            .init_resource::<CurrentRun>()
+            .init_resource::<GameMode>()
+            .insert_resource(GameRng(StdRng::seed_from_u64(0)))
+            .add_systems(OnEnter(GameState::Game), start_run)
+            // After the fixed ticks, so the last pipe before dying counts before the death screen
+            .add_systems(PostUpdate, record_highscore.run_if(on_event::<ScoreEvent>))
+            .add_systems(
+                FixedUpdate,
+                // Stops on the tick the bird crashes, so that's where replays end
+                tick_run
+                    .after(PhysicsSet::Collide)
+                    .run_if(in_state(PlayState::Running).and(player::bird_alive)),
+            )
             // These should only really work if State is Game
             .add_plugins(player::PlayerPlugin)
             .add_plugins(pipes::PipesPlugin);
     }
 }
 
-#[derive(Resource, Deref)]
-pub struct FlopSound(Handle<AudioSource>);
-#[derive(Resource, Deref)]
-pub struct DeathSound(Handle<AudioSource>);
-#[derive(Resource, Deref)]
-pub struct WohoSound(Handle<AudioSource>);
-
-#[derive(Component)]
-struct BackgroundTile;
-
-const BACKGROUND_WIDTH: f32 = 400.; //1024.;
-const BACKGROUND_SPEED: f32 = 100.;
-
-fn game_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
-    let flop_sound =
-        asset_server.load::<AudioSource>("embedded://flappyboi/../assets/audio/flop.ogg");
-    let death_sound =
-        asset_server.load::<AudioSource>("embedded://flappyboi/../assets/audio/death.ogg");
-    let woho_sound =
-        asset_server.load::<AudioSource>("embedded://flappyboi/../assets/audio/woho.ogg");
-
-    commands.insert_resource(FlopSound(flop_sound));
-    commands.insert_resource(DeathSound(death_sound));
-    commands.insert_resource(WohoSound(woho_sound));
-
-    commands.spawn((
-        BackgroundTile,
-        Sprite {
-            image: asset_server.load("embedded://flappyboi/../assets/bg.png"),
-            custom_size: Some(Vec2::new(400., 400.)),
-            anchor: bevy::sprite::Anchor::BottomLeft,
-            ..default()
-        },
-        Transform {
-            translation: Vec3::new(0., 0., -10.),
-            ..default()
-        },
-    ));
-
-    commands.spawn((
-        BackgroundTile,
-        Sprite {
-            image: asset_server.load("embedded://flappyboi/../assets/bg.png"),
-            custom_size: Some(Vec2::new(400., 400.)),
-            anchor: bevy::sprite::Anchor::BottomLeft,
-            ..default()
-        },
-        Transform {
-            translation: Vec3::new(BACKGROUND_WIDTH, 0., -10.),
-            ..default()
-        },
-    ));
+/// Which kind of run is being played, picked from the menu.
+#[derive(Resource, Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GameMode {
+    #[default]
+    Classic,
+    /// Same pipes for everyone today, on the Normal preset. Has its own best score and
+    /// stays off the highscores and leaderboards.
+    Daily,
+    /// Watching a recorded run on the preset it was played on. Nothing it does counts.
+    Replay(Preset),
+    /// The autopilot flying on the selected preset, as the menu's attract mode. Nothing it
+    /// does counts either.
+    Demo,
+    /// A population of birds learning to fly on the selected preset, one generation per run.
+    Evolution,
+    /// Several players racing their own birds on one keyboard, on the selected preset.
+    Versus,
+    /// Racing other instances on the network, on the course and preset the host picked.
+    Race { seed: u64, preset: Preset },
 }
 
-fn move_background(mut background_q: Query<&mut Transform, With<BackgroundTile>>, time: Res<Time>) {
-    for mut transform in background_q.iter_mut() {
-        transform.translation.x -= BACKGROUND_SPEED * time.delta_secs();
-        if transform.translation.x < -BACKGROUND_WIDTH {
-            transform.translation.x = BACKGROUND_WIDTH;
+impl GameMode {
+    /// Whether the player is the one flying, so the run counts for scores, stats and replays.
+    pub fn is_played(self) -> bool {
+        matches!(self, GameMode::Classic | GameMode::Daily)
+    }
+
+    /// Whether the flaps come from the player's input, counted or not.
+    pub fn takes_input(self) -> bool {
+        self.is_played() || matches!(self, GameMode::Race { .. })
+    }
+}
+
+/// Run condition for everything that should only happen in runs the player flies.
+pub fn player_playing(mode: Res<GameMode>) -> bool {
+    mode.is_played()
+}
+
+/// Run condition for the player's flaps going to their bird.
+pub fn player_flying(mode: Res<GameMode>) -> bool {
+    mode.takes_input()
+}
+
+/// Run condition for races over the network.
+pub fn racing(mode: Res<GameMode>) -> bool {
+    matches!(*mode, GameMode::Race { .. })
+}
+
+/// The best score to beat in the current mode.
+#[derive(SystemParam)]
+pub struct BestScore<'w> {
+    mode: Res<'w, GameMode>,
+    preset: Res<'w, Preset>,
+    highscores: Res<'w, Highscores>,
+    daily: Res<'w, DailyChallenge>,
+    run: Res<'w, CurrentRun>,
+}
+
+impl BestScore<'_> {
+    pub fn mode(&self) -> GameMode {
+        *self.mode
+    }
+
+    pub fn get(&self) -> usize {
+        match *self.mode {
+            GameMode::Classic | GameMode::Demo | GameMode::Evolution | GameMode::Versus => {
+                self.highscores.get(*self.preset)
+            }
+            GameMode::Daily => self.run.day.map_or(0, |day| self.daily.on(day).best),
+            GameMode::Replay(preset) | GameMode::Race { preset, .. } => self.highscores.get(preset),
         }
     }
 }
+
+/// Source of everything random that affects gameplay. It's reseeded at the start of every
+/// run, so the same seed always gives the same pipes. Purely cosmetic randomness, like sound
+/// pitch, stays on `thread_rng` so it can't throw the sequence off.
+#[derive(Resource, Deref, DerefMut)]
+pub struct GameRng(StdRng);
+
+/// Bookkeeping for the run in progress, reset whenever a new one starts.
+#[derive(Resource, Default)]
+pub struct CurrentRun {
+    pub duration: Stopwatch,
+    pub flaps: u32,
+    pub seed: Option<u64>,
+    /// The day whose daily challenge is played, so a run past midnight still counts for it.
+    pub day: Option<u64>,
+    /// Fixed ticks the bird hovered for before the first flap.
+    pub ready_ticks: u64,
+    /// Fixed ticks since the first flap, the clock replays are recorded against.
+    pub ticks: u64,
+    /// The highscore before this run started, to tell whether it set a new best.
+    pub starting_highscore: usize,
+}
+
+pub fn start_run(
+    mut score: ResMut<Score>,
+    // The best score to beat depends on the day the run is for
+    mut run_and_best: ParamSet<(ResMut<CurrentRun>, BestScore)>,
+    mut rng: ResMut<GameRng>,
+    mode: Res<GameMode>,
+    args: Res<CliArgs>,
+    playback: Option<Res<ReplayPlayback>>,
+) {
+    let day = (*mode == GameMode::Daily).then(daily::today);
+    let seed = match *mode {
+        GameMode::Daily => daily::seed_for_day(day.unwrap_or_default()),
+        GameMode::Replay(_) => playback.map_or(0, |playback| playback.replay.seed),
+        GameMode::Race { seed, .. } => seed,
+        // Random seeds are kept small enough to read out and type back in
+        GameMode::Classic | GameMode::Demo | GameMode::Evolution | GameMode::Versus => args
+            .seed
+            .unwrap_or_else(|| rand::thread_rng().gen_range(0..1_000_000_000)),
+    };
+    info!("Starting run with seed {}", seed);
+
+    **score = 0;
+    rng.0 = StdRng::seed_from_u64(seed);
+    *run_and_best.p0() = CurrentRun {
+        seed: Some(seed),
+        day,
+        ..default()
+    };
+    let starting_highscore = run_and_best.p1().get();
+    run_and_best.p0().starting_highscore = starting_highscore;
+}
+
+// Going from `Game` to `Game` doesn't run any transition schedules, so the run is torn
+// down and set up again by hand
+pub fn restart_run(world: &mut World) {
+    world.run_schedule(OnExit(GameState::Game));
+    world.run_schedule(OnEnter(GameState::Game));
+}
+
+fn tick_run(mut run: ResMut<CurrentRun>, mut jump_events: EventReader<JumpEvent>, time: Res<Time>) {
+    run.ticks += 1;
+    run.duration.tick(time.delta());
+    run.flaps += jump_events.read().count() as u32;
+}
+
+fn record_highscore(
+    mut score_events: EventReader<ScoreEvent>,
+    mode: Res<GameMode>,
+    mut highscores: ResMut<Highscores>,
+    mut daily: ResMut<DailyChallenge>,
+    preset: Res<Preset>,
+    run: Res<CurrentRun>,
+) {
+    for event in score_events.read() {
+        let best = match *mode {
+            GameMode::Classic => highscores.0.entry(*preset).or_default(),
+            GameMode::Daily => match run.day {
+                Some(day) => &mut daily.on_mut(day).best,
+                None => continue,
+            },
+            GameMode::Replay(_)
+            | GameMode::Demo
+            | GameMode::Evolution
+            | GameMode::Versus
+            | GameMode::Race { .. } => continue,
+        };
+        *best = (*best).max(event.score);
+    }
+}
@@ -0,0 +1,143 @@
+use super::{despawn_screen, GameState, PlayState};
+use crate::difficulty::{difficulty_at, Difficulty, DifficultySettings};
+use crate::game::{player_playing, start_run, CurrentRun};
+use crate::physics::{PhysicsSet, Position};
+use crate::player::{hover_height, Flight, BIRD_X};
+use crate::replay::Replay;
+use crate::settings::Settings;
+use crate::tuning::GameTuning;
+use bevy::prelude::*;
+
+const GHOST_COLOR: Color = Color::srgba(1., 1., 1., 0.4);
+
+pub struct GhostPlugin;
+
+impl Plugin for GhostPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ChosenGhost>()
+            .add_systems(
+                OnEnter(GameState::Game),
+                spawn_ghost.after(start_run).run_if(player_playing),
+            )
+            .add_systems(OnExit(GameState::Game), despawn_screen::<Ghost>)
+            .add_systems(
+                FixedUpdate,
+                fly_ghost
+                    .in_set(PhysicsSet::Move)
+                    .run_if(in_state(PlayState::Running)),
+            )
+            .add_systems(
+                Update,
+                update_ghost_visibility.run_if(in_state(GameState::Game)),
+            );
+    }
+}
+
+/// Replay picked on the replay screen to race against instead of the personal best.
+#[derive(Resource, Default)]
+pub struct ChosenGhost(pub Option<Replay>);
+
+/// A bird flying a recorded run next to the live one. It's only drawn, it never collides
+/// or scores.
+#[derive(Component)]
+struct Ghost {
+    flight: Flight,
+    /// Of the preset the replay was recorded on, which might not be the one being played.
+    difficulty: Difficulty,
+    jumps: Vec<u64>,
+    next_jump: usize,
+    /// The tick the recorded bird crashed on, the ghost is gone after it.
+    last_tick: u64,
+}
+
+fn spawn_ghost(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    chosen_ghost: Res<ChosenGhost>,
+    difficulty: DifficultySettings,
+    tuning: Res<GameTuning>,
+    fixed_time: Res<Time<Fixed>>,
+) {
+    let Some(replay) = chosen_ghost
+        .0
+        .clone()
+        .or_else(|| Replay::load_best(difficulty.preset()))
+    else {
+        return;
+    };
+
+    // It starts from wherever the recorded bird was when it took off
+    let start_y = hover_height(
+        replay.ready_ticks,
+        fixed_time.timestep().as_secs_f32(),
+        &tuning.bird,
+    );
+    commands.spawn((
+        Ghost {
+            flight: Flight::default(),
+            difficulty: difficulty_at(&tuning, replay.preset, 0),
+            jumps: replay.jumps,
+            next_jump: 0,
+            last_tick: replay.ticks,
+        },
+        Sprite {
+            image: asset_server.load("embedded://flappyboi/../assets/bird.png"),
+            color: GHOST_COLOR,
+            ..default()
+        },
+        Position::new(Vec2::new(BIRD_X, start_y)),
+        // Just behind the live bird
+        Transform::from_xyz(BIRD_X, start_y, 4.),
+        Visibility::Hidden,
+    ));
+}
+
+fn fly_ghost(
+    mut commands: Commands,
+    run: Res<CurrentRun>,
+    time: Res<Time>,
+    tuning: Res<GameTuning>,
+    ghost_q: Single<(Entity, &mut Ghost, &mut Position, &mut Transform)>,
+) {
+    let (entity, mut ghost, mut position, mut transform) = ghost_q.into_inner();
+    if run.ticks > ghost.last_tick {
+        commands.entity(entity).despawn();
+        return;
+    }
+
+    let ghost = &mut *ghost;
+    let mut flapped = false;
+    while ghost
+        .jumps
+        .get(ghost.next_jump)
+        .is_some_and(|&tick| tick <= run.ticks)
+    {
+        ghost.next_jump += 1;
+        flapped = true;
+    }
+
+    ghost.flight.step(
+        flapped,
+        time.delta_secs(),
+        &ghost.difficulty,
+        &tuning.bird,
+        &mut position,
+        &mut transform,
+    );
+}
+
+// Hidden while getting ready, since the ghost only takes off with the live bird
+fn update_ghost_visibility(
+    settings: Res<Settings>,
+    play_state: Res<State<PlayState>>,
+    mut ghost_q: Query<&mut Visibility, With<Ghost>>,
+) {
+    let visible = settings.ghost && *play_state.get() != PlayState::GetReady;
+    for mut visibility in ghost_q.iter_mut() {
+        visibility.set_if_neq(if visible {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        });
+    }
+}
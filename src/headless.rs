@@ -0,0 +1,352 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use super::{GameState, Highscores, PlayState, Score};
+use crate::autopilot;
+use crate::cli::CliArgs;
+use crate::daily::DailyChallenge;
+use crate::difficulty::{self, Preset};
+use crate::game::{self, CurrentRun, GameMode};
+use crate::physics::{self, TICK_RATE};
+use crate::player::{DeathCause, DeathEvent};
+use crate::replay::{self, Replay, ReplayPlayback};
+use crate::tuning::GameTuning;
+use bevy::log::LogPlugin;
+use bevy::prelude::*;
+use bevy::state::app::StatesPlugin;
+use bevy::time::TimeUpdateStrategy;
+use rand::Rng;
+
+// A run that's still going after an hour of game time isn't going to end
+const MAX_UPDATES: u64 = 60 * 60 * TICK_RATE as u64;
+
+/// How the run ended, filled in when the bird crashes.
+#[derive(Resource)]
+struct Outcome {
+    cause: DeathCause,
+}
+
+/// How a simulated run went.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Report {
+    pub seed: u64,
+    pub score: usize,
+    /// `None` while it's still flying, after reaching the score it was to stop at.
+    pub death: Option<DeathCause>,
+    pub ticks: u64,
+}
+
+/// The gameplay with no window, audio or save, where every update is exactly one fixed tick.
+/// It waits in the menu until something sets `GameState::Game`, with the `GameMode`, `Preset`
+/// and `CliArgs` for the run in place.
+pub fn simulation() -> App {
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, LogPlugin::default(), StatesPlugin))
+        .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(
+            1. / TICK_RATE,
+        )))
+        .insert_resource(GameTuning::built_in())
+        .init_resource::<Highscores>()
+        .init_resource::<DailyChallenge>()
+        .insert_state(GameState::Menu)
+        .add_sub_state::<PlayState>()
+        .add_plugins((
+            physics::PhysicsPlugin,
+            difficulty::DifficultyPlugin,
+            game::GamePlugin,
+            replay::PlaybackPlugin,
+            autopilot::AutopilotPlugin,
+        ));
+    app
+}
+
+/// Plays a single run as fast as the simulation goes and prints how it went. The flaps come
+/// from `--inputs` or `--replay`, or the autopilot with `--autopilot`.
+pub fn run() -> AppExit {
+    let mut app = simulation();
+
+    // Parsed after the log plugin is in, so mistakes in the arguments get reported
+    let args = CliArgs::parse();
+    if args.autopilot {
+        app.insert_resource(args.preset.unwrap_or(Preset::Normal))
+            .insert_resource(GameMode::Demo);
+    } else {
+        let Some(replay) = script(&args, app.world().resource::<GameTuning>()) else {
+            return AppExit::error();
+        };
+        play_back(&mut app, replay);
+    }
+    let until = args.until;
+    app.insert_resource(args);
+
+    let Some(report) = fly(&mut app, until) else {
+        error!(
+            "The run was still going after {} ticks, giving up",
+            MAX_UPDATES
+        );
+        return AppExit::error();
+    };
+    // Still flying when it reached `--until`
+    let cause = match report.death {
+        Some(DeathCause::Ground) => "ground",
+        Some(DeathCause::Pipe) => "pipe",
+        None => "none",
+    };
+    println!("seed: {}", report.seed);
+    println!("score: {}", report.score);
+    println!("death: {}", cause);
+    println!("ticks: {}", report.ticks);
+    if let Some(pipe) = app.world().resource::<autopilot::Impassable>().0 {
+        println!("impassable: {}", pipe);
+    }
+    AppExit::Success
+}
+
+/// Sets up `app` to play `replay` back as its run.
+pub fn play_back(app: &mut App, replay: Replay) {
+    app.insert_resource(replay.preset)
+        .insert_resource(GameMode::Replay(replay.preset))
+        .insert_resource(ReplayPlayback::new(replay));
+}
+
+/// Starts the run `app` was set up for and plays it until the bird crashes or the score
+/// reaches `until`. `None` if it's still going after an hour of game time.
+pub fn fly(app: &mut App, until: Option<usize>) -> Option<Report> {
+    app.add_systems(PostUpdate, record_outcome.run_if(on_event::<DeathEvent>));
+    app.world_mut()
+        .resource_mut::<NextState<GameState>>()
+        .set(GameState::Game);
+
+    app.finish();
+    app.cleanup();
+    let mut updates = 0;
+    while !app.world().contains_resource::<Outcome>() {
+        if until.is_some_and(|until| **app.world().resource::<Score>() >= until) {
+            break;
+        }
+        if updates == MAX_UPDATES {
+            return None;
+        }
+        app.update();
+        updates += 1;
+    }
+
+    let world = app.world();
+    let run = world.resource::<CurrentRun>();
+    Some(Report {
+        seed: run.seed.unwrap_or_default(),
+        score: **world.resource::<Score>(),
+        death: world.get_resource::<Outcome>().map(|outcome| outcome.cause),
+        ticks: run.ticks,
+    })
+}
+
+fn record_outcome(mut commands: Commands, mut death_events: EventReader<DeathEvent>) {
+    if let Some(event) = death_events.read().next() {
+        commands.insert_resource(Outcome { cause: event.cause });
+    }
+}
+
+/// The run to simulate, as a replay: either the one from `--replay`, or the seed, preset and
+/// flaps from the other arguments.
+fn script(args: &CliArgs, tuning: &GameTuning) -> Option<Replay> {
+    if let Some(path) = &args.replay {
+        return match Replay::load(path) {
+            // Other tuning plays out differently, which would defeat reproducing the run
+            Ok(replay) if replay.tuning_version != tuning.version => {
+                error!(
+                    "Replay {} was recorded with tuning version {}, this is version {}",
+                    path.display(),
+                    replay.tuning_version,
+                    tuning.version
+                );
+                None
+            }
+            Ok(replay) => Some(replay),
+            Err(e) => {
+                error!("Failed to load replay {}: {}", path.display(), e);
+                None
+            }
+        };
+    }
+
+    let jumps = match &args.inputs {
+        Some(path) => match read_inputs(path) {
+            Ok(jumps) => jumps,
+            Err(e) => {
+                error!("Failed to read inputs {}: {}", path.display(), e);
+                return None;
+            }
+        },
+        None => Vec::new(),
+    };
+
+    Some(Replay {
+        seed: args
+            .seed
+            .unwrap_or_else(|| rand::thread_rng().gen_range(0..1_000_000_000)),
+        preset: args.preset.unwrap_or(Preset::Normal),
+        tuning_version: tuning.version,
+        ready_ticks: 0,
+        jumps,
+        ticks: 0,
+        score: 0,
+        recorded_at: 0,
+    })
+}
+
+/// Reads an input script: the fixed ticks to flap on, separated by whitespace, with `#`
+/// starting a comment. The run always starts with a flap on tick 0.
+fn read_inputs(path: &Path) -> io::Result<Vec<u64>> {
+    let contents = fs::read_to_string(path)?;
+    let mut jumps = contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .flat_map(str::split_whitespace)
+        .map(|tick| {
+            tick.parse()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{tick}: {e}")))
+        })
+        .collect::<io::Result<Vec<u64>>>()?;
+    jumps.sort_unstable();
+    Ok(jumps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::JumpEvent;
+    use crate::physics::PhysicsSet;
+    use std::f32::consts::FRAC_PI_2;
+
+    const SEEDS: u64 = 50;
+    // Enough pipe pairs for every preset to get some way up its curve
+    const AUTOPILOT_SEEDS: u64 = 8;
+    const AUTOPILOT_PIPES: usize = 25;
+    // Flapping this often climbs on every preset, so runs end at the ceiling or a pipe
+    const FLAP_EVERY: u64 = 40;
+    const RECORDED_SCORE: usize = 10;
+
+    /// Ticks the bird flapped on, like the game's recording, which isn't in the simulation.
+    #[derive(Resource, Default)]
+    struct Flaps(Vec<u64>);
+
+    fn record_flaps(
+        mut jump_events: EventReader<JumpEvent>,
+        mut flaps: ResMut<Flaps>,
+        run: Res<CurrentRun>,
+    ) {
+        if !jump_events.is_empty() {
+            jump_events.clear();
+            flaps.0.push(run.ticks);
+        }
+    }
+
+    fn replay(seed: u64, preset: Preset, jumps: Vec<u64>) -> Replay {
+        Replay {
+            seed,
+            preset,
+            tuning_version: GameTuning::built_in().version,
+            ready_ticks: 0,
+            jumps,
+            ticks: 0,
+            score: 0,
+            recorded_at: 0,
+        }
+    }
+
+    fn play(replay: Replay) -> Report {
+        let mut app = simulation();
+        play_back(&mut app, replay);
+        app.insert_resource(CliArgs::default());
+        fly(&mut app, None).expect("the run should end")
+    }
+
+    // The bird has to be able to get down to any opening after the flap that starts the run,
+    // even from the top of the bob
+    #[test]
+    fn falls_to_the_ground_before_the_first_pipe() {
+        let bird = GameTuning::built_in().bird;
+        let top_of_bob = (FRAC_PI_2 / bird.hover_speed * TICK_RATE as f32).round() as u64;
+        for preset in Preset::ALL {
+            for seed in 0..SEEDS {
+                let mut replay = replay(seed, preset, Vec::new());
+                replay.ready_ticks = top_of_bob;
+                let report = play(replay);
+                assert_eq!(
+                    report.death,
+                    Some(DeathCause::Ground),
+                    "seed {} on {:?}",
+                    seed,
+                    preset
+                );
+            }
+        }
+    }
+    #[test]
+    fn autopilot_gets_through_every_preset() {
+        for preset in Preset::ALL {
+            for seed in 0..AUTOPILOT_SEEDS {
+                let mut app = simulation();
+                app.insert_resource(preset)
+                    .insert_resource(GameMode::Demo)
+                    .insert_resource(CliArgs {
+                        seed: Some(seed),
+                        ..default()
+                    });
+                let report = fly(&mut app, Some(AUTOPILOT_PIPES)).expect("the run should end");
+                let impassable = app.world().resource::<autopilot::Impassable>().0;
+                assert_eq!(impassable, None, "seed {} on {:?}", seed, preset);
+                assert_eq!(report.death, None, "seed {} on {:?}", seed, preset);
+            }
+        }
+    }
+    #[test]
+    fn same_seed_and_flaps_play_out_the_same() {
+        let jumps: Vec<u64> = (0..MAX_UPDATES).step_by(FLAP_EVERY as usize).collect();
+        for preset in Preset::ALL {
+            for seed in 0..SEEDS {
+                let first = play(replay(seed, preset, jumps.clone()));
+                let second = play(replay(seed, preset, jumps.clone()));
+                assert_eq!(first, second, "seed {} on {:?}", seed, preset);
+            }
+        }
+    }
+
+    #[test]
+    fn recorded_replay_plays_back_its_score() {
+        let seed = 7;
+        let mut app = simulation();
+        app.insert_resource(Preset::Normal)
+            .insert_resource(GameMode::Demo)
+            .insert_resource(CliArgs {
+                seed: Some(seed),
+                ..default()
+            })
+            .init_resource::<Flaps>()
+            .add_systems(
+                FixedUpdate,
+                record_flaps
+                    .in_set(PhysicsSet::Move)
+                    .run_if(in_state(PlayState::Running)),
+            );
+        fly(&mut app, Some(RECORDED_SCORE)).expect("the run should end");
+        // Nobody flaps for a classic run in the simulation, so the bird falls from there
+        app.insert_resource(GameMode::Classic);
+        while !app.world().contains_resource::<Outcome>() {
+            app.update();
+        }
+
+        let world = app.world();
+        let run = world.resource::<CurrentRun>();
+        let mut recorded = replay(seed, Preset::Normal, world.resource::<Flaps>().0.clone());
+        recorded.ready_ticks = run.ready_ticks;
+        recorded.ticks = run.ticks;
+        recorded.score = **world.resource::<Score>();
+        let report = play(recorded.clone());
+        assert_eq!(report.score, recorded.score);
+        assert_eq!(report.ticks, recorded.ticks);
+    }
+}
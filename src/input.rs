@@ -1,20 +1,398 @@
-use crate::GameState;
+use std::collections::BTreeMap;
+
+use crate::game::player_flying;
+use crate::persistence::SaveRequested;
+use crate::settings::Settings;
+use crate::{GameState, PlayState};
+use bevy::ecs::system::SystemParam;
+use bevy::input::gamepad::{GamepadConnection, GamepadConnectionEvent};
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
 #[derive(Default, Event)]
 pub struct JumpEvent;
 
+// Taps are ignored for this long after the screen changes, so the tap that changed it
+// can't count a second time on the new screen
+const POINTER_DEBOUNCE_SECS: f32 = 0.2;
+
+// How far the stick has to be pushed to count as up/down in menus, and how far it has to
+// come back before it can count again
+const STICK_PRESS_THRESHOLD: f32 = 0.6;
+const STICK_RELEASE_THRESHOLD: f32 = 0.3;
+
 pub struct InputPlugin;
 
 impl Plugin for InputPlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<JumpEvent>()
-            .add_systems(Update, (handle_input).run_if(in_state(GameState::Game)));
+            .insert_resource(PointerDebounce(Timer::from_seconds(
+                POINTER_DEBOUNCE_SECS,
+                TimerMode::Once,
+            )))
+            .add_systems(
+                PreUpdate,
+                (
+                    reset_pointer_debounce.run_if(state_changed::<GameState>),
+                    tick_pointer_debounce,
+                )
+                    .chain(),
+            )
+            // Flaps are sent before `Update`, so everything in there sees them the same frame
+            .add_systems(
+                PreUpdate,
+                (
+                    handle_gamepad_connections,
+                    update_stick_navigation,
+                    // Replays and the autopilot do their own flapping
+                    handle_input.run_if(
+                        in_state(PlayState::GetReady)
+                            .or(in_state(PlayState::Running))
+                            .and(player_flying),
+                    ),
+                )
+                    .chain()
+                    .after(bevy::input::InputSystem),
+            );
+    }
+}
+
+/// Everything the player can do, independent of which keys do it.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
+pub enum Action {
+    Flap,
+    Confirm,
+    Pause,
+    Back,
+    Quit,
+    Up,
+    Down,
+}
+
+impl Action {
+    pub const ALL: [Action; 7] = [
+        Action::Flap,
+        Action::Confirm,
+        Action::Pause,
+        Action::Back,
+        Action::Quit,
+        Action::Up,
+        Action::Down,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Flap => "Flap",
+            Action::Confirm => "Confirm",
+            Action::Pause => "Pause",
+            Action::Back => "Back",
+            Action::Quit => "Quit",
+            Action::Up => "Up",
+            Action::Down => "Down",
+        }
+    }
+
+    fn default_gamepad_buttons(self) -> Vec<GamepadButton> {
+        match self {
+            Action::Flap => vec![GamepadButton::South],
+            Action::Confirm => vec![GamepadButton::South],
+            Action::Pause => vec![GamepadButton::Start],
+            Action::Back => vec![GamepadButton::East, GamepadButton::Select],
+            Action::Quit => vec![],
+            Action::Up => vec![GamepadButton::DPadUp],
+            Action::Down => vec![GamepadButton::DPadDown],
+        }
+    }
+
+    fn default_keys(self) -> Vec<KeyCode> {
+        match self {
+            Action::Flap => vec![KeyCode::Space],
+            Action::Confirm => vec![KeyCode::Space, KeyCode::Enter],
+            Action::Pause => vec![KeyCode::KeyP],
+            Action::Back => vec![KeyCode::Escape, KeyCode::Backspace],
+            // Escape already goes back, and backing out of the death screen shouldn't close the game
+            Action::Quit => vec![],
+            Action::Up => vec![KeyCode::ArrowUp, KeyCode::KeyW],
+            Action::Down => vec![KeyCode::ArrowDown, KeyCode::KeyS],
+        }
+    }
+}
+
+/// Which keys trigger which action, several keys can share an action.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(
+    from = "BTreeMap<Action, Vec<KeyCode>>",
+    into = "BTreeMap<Action, Vec<KeyCode>>"
+)]
+pub struct Bindings(BTreeMap<Action, Vec<KeyCode>>);
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Self(
+            Action::ALL
+                .into_iter()
+                .map(|action| (action, action.default_keys()))
+                .collect(),
+        )
+    }
+}
+
+// Saves from before an action existed get the default keys for it, and so do actions a
+// hand-edited save left without any key. Older saves still have Escape on Quit too, which
+// shares it with Back.
+impl From<BTreeMap<Action, Vec<KeyCode>>> for Bindings {
+    fn from(mut keys: BTreeMap<Action, Vec<KeyCode>>) -> Self {
+        if keys.get(&Action::Quit) == Some(&vec![KeyCode::Escape]) {
+            keys.remove(&Action::Quit);
+        }
+        for action in Action::ALL {
+            let bound = keys.entry(action).or_default();
+            if bound.is_empty() {
+                *bound = action.default_keys();
+            }
+        }
+        Self(keys)
+    }
+}
+
+impl From<Bindings> for BTreeMap<Action, Vec<KeyCode>> {
+    fn from(bindings: Bindings) -> Self {
+        bindings.0
+    }
+}
+
+impl Bindings {
+    pub fn keys(&self, action: Action) -> &[KeyCode] {
+        self.0.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    /// Binds the key if it isn't bound to the action yet, unbinds it otherwise. The last key
+    /// of an action is never removed, so no action can become unreachable, unless the action
+    /// has no keys by default.
+    pub fn toggle(&mut self, action: Action, key: KeyCode) {
+        let optional = action.default_keys().is_empty();
+        let keys = self.0.entry(action).or_default();
+        match keys.iter().position(|&bound| bound == key) {
+            Some(index) if keys.len() > 1 || optional => {
+                keys.remove(index);
+            }
+            Some(_) => {}
+            None => keys.push(key),
+        }
+    }
+
+    pub fn just_pressed(&self, keys: &ButtonInput<KeyCode>, action: Action) -> bool {
+        keys.any_just_pressed(self.keys(action).iter().copied())
+    }
+
+    /// Human readable list of the keys for an action, like "Space / Enter".
+    pub fn describe(&self, action: Action) -> String {
+        self.keys(action)
+            .iter()
+            .map(|&key| key_name(key))
+            .collect::<Vec<_>>()
+            .join(" / ")
+    }
+}
+
+/// Button layout for one kind of controller. Profiles are kept per controller name, so
+/// every model of pad remembers its own.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+#[serde(
+    from = "BTreeMap<Action, Vec<GamepadButton>>",
+    into = "BTreeMap<Action, Vec<GamepadButton>>"
+)]
+pub struct GamepadBindings(BTreeMap<Action, Vec<GamepadButton>>);
+
+impl Default for GamepadBindings {
+    fn default() -> Self {
+        Self(
+            Action::ALL
+                .into_iter()
+                .map(|action| (action, action.default_gamepad_buttons()))
+                .collect(),
+        )
+    }
+}
+
+impl From<BTreeMap<Action, Vec<GamepadButton>>> for GamepadBindings {
+    fn from(mut buttons: BTreeMap<Action, Vec<GamepadButton>>) -> Self {
+        for action in Action::ALL {
+            buttons
+                .entry(action)
+                .or_insert_with(|| action.default_gamepad_buttons());
+        }
+        Self(buttons)
+    }
+}
+
+impl From<GamepadBindings> for BTreeMap<Action, Vec<GamepadButton>> {
+    fn from(bindings: GamepadBindings) -> Self {
+        bindings.0
+    }
+}
+
+impl GamepadBindings {
+    pub fn buttons(&self, action: Action) -> &[GamepadButton] {
+        self.0.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    /// Like `Bindings::toggle`, the last button of an action stays.
+    pub fn toggle(&mut self, action: Action, button: GamepadButton) {
+        let buttons = self.0.entry(action).or_default();
+        match buttons.iter().position(|&bound| bound == button) {
+            Some(index) if buttons.len() > 1 => {
+                buttons.remove(index);
+            }
+            Some(_) => {}
+            None => buttons.push(button),
+        }
+    }
+
+    pub fn just_pressed(&self, gamepad: &Gamepad, action: Action) -> bool {
+        gamepad.any_just_pressed(self.buttons(action).iter().copied())
+    }
+
+    /// Human readable list of the buttons for an action, like "East / Select".
+    pub fn describe(&self, action: Action) -> String {
+        self.buttons(action)
+            .iter()
+            .map(|button| format!("{:?}", button))
+            .collect::<Vec<_>>()
+            .join(" / ")
+    }
+}
+
+/// Turns the left stick into up/down presses for menus.
+#[derive(Component, Default)]
+struct StickNavigation {
+    up_held: bool,
+    down_held: bool,
+    up_just_pressed: bool,
+    down_just_pressed: bool,
+}
+
+pub fn key_name(key: KeyCode) -> String {
+    let name = format!("{:?}", key);
+    // KeyA -> A, Digit1 -> 1, everything else already reads fine
+    name.strip_prefix("Key")
+        .or_else(|| name.strip_prefix("Digit"))
+        .filter(|rest| !rest.is_empty())
+        .unwrap_or(&name)
+        .to_string()
+}
+
+#[derive(Resource)]
+struct PointerDebounce(Timer);
+
+/// Asks about actions instead of keys, so systems never care what the player bound or
+/// which device they are holding.
+#[derive(SystemParam)]
+pub struct ActionInput<'w, 's> {
+    keys: Res<'w, ButtonInput<KeyCode>>,
+    mouse: Res<'w, ButtonInput<MouseButton>>,
+    touches: Res<'w, Touches>,
+    pointer_debounce: Res<'w, PointerDebounce>,
+    settings: Res<'w, Settings>,
+    gamepads: Query<
+        'w,
+        's,
+        (
+            &'static Gamepad,
+            &'static GamepadBindings,
+            &'static StickNavigation,
+        ),
+    >,
+}
+
+impl ActionInput<'_, '_> {
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.settings.bindings.just_pressed(&self.keys, action) || self.gamepad_just_pressed(action)
+    }
+
+    /// Only asks the controllers, for screens where the keyboard is busy typing.
+    pub fn gamepad_just_pressed(&self, action: Action) -> bool {
+        self.gamepads.iter().any(|(gamepad, bindings, stick)| {
+            bindings.just_pressed(gamepad, action)
+                || (action == Action::Up && stick.up_just_pressed)
+                || (action == Action::Down && stick.down_just_pressed)
+        })
+    }
+
+    /// A left click or a finger touching the screen. Screens that don't have buttons treat
+    /// this like flap or confirm.
+    pub fn pointer_just_pressed(&self) -> bool {
+        self.pointer_debounce.0.finished()
+            && (self.mouse.just_pressed(MouseButton::Left) || self.touches.any_just_pressed())
+    }
+}
+
+fn reset_pointer_debounce(mut debounce: ResMut<PointerDebounce>) {
+    debounce.0.reset();
+}
+
+fn tick_pointer_debounce(mut debounce: ResMut<PointerDebounce>, time: Res<Time>) {
+    debounce.0.tick(time.delta());
+}
+
+// Controllers get their profile when they show up, and lose it again when unplugged
+fn handle_gamepad_connections(
+    mut commands: Commands,
+    mut connection_events: EventReader<GamepadConnectionEvent>,
+    mut settings: ResMut<Settings>,
+    mut save_requests: EventWriter<SaveRequested>,
+) {
+    for event in connection_events.read() {
+        match &event.connection {
+            GamepadConnection::Connected { name, .. } => {
+                info!("Gamepad connected: {}", name);
+                let profile = settings
+                    .gamepad_profiles
+                    .entry(name.clone())
+                    .or_insert_with(|| {
+                        save_requests.send_default();
+                        GamepadBindings::default()
+                    })
+                    .clone();
+
+                commands
+                    .entity(event.gamepad)
+                    .insert((profile, StickNavigation::default()));
+            }
+            GamepadConnection::Disconnected => {
+                info!("Gamepad disconnected");
+                if let Some(mut entity) = commands.get_entity(event.gamepad) {
+                    entity.remove::<(GamepadBindings, StickNavigation)>();
+                }
+            }
+        }
+    }
+}
+
+fn update_stick_navigation(mut gamepad_q: Query<(&Gamepad, &mut StickNavigation)>) {
+    for (gamepad, mut stick) in gamepad_q.iter_mut() {
+        let y = gamepad.left_stick().y;
+
+        let up_held = if stick.up_held {
+            y > STICK_RELEASE_THRESHOLD
+        } else {
+            y > STICK_PRESS_THRESHOLD
+        };
+        let down_held = if stick.down_held {
+            y < -STICK_RELEASE_THRESHOLD
+        } else {
+            y < -STICK_PRESS_THRESHOLD
+        };
+
+        stick.up_just_pressed = up_held && !stick.up_held;
+        stick.down_just_pressed = down_held && !stick.down_held;
+        stick.up_held = up_held;
+        stick.down_held = down_held;
     }
 }
 
-fn handle_input(keys: Res<ButtonInput<KeyCode>>, mut jump_event: EventWriter<JumpEvent>) {
-    if keys.just_pressed(KeyCode::Space) {
+fn handle_input(input: ActionInput, mut jump_event: EventWriter<JumpEvent>) {
+    if input.just_pressed(Action::Flap) || input.pointer_just_pressed() {
         jump_event.send_default();
     }
 }
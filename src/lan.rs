@@ -0,0 +1,1113 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+use super::{despawn_screen, GameState, PlayState, Score};
+use crate::difficulty::Preset;
+use crate::game::{racing, restart_run, CurrentRun, GameMode};
+use crate::input::{Action, ActionInput};
+use crate::persistence::SaveRequested;
+use crate::physics::{PhysicsSet, Position};
+use crate::player::{DeathEvent, Player, BIRD_X};
+use crate::settings::Settings;
+use crate::versus::{MAX_PLAYERS, TEXT_TINTS, TINTS};
+use bevy::input::keyboard::{Key, KeyboardInput};
+use bevy::input::ButtonState;
+use bevy::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// The host listens here, guests take whatever port they get.
+pub const PORT: u16 = 47474;
+// Plenty for any message, they're a few dozen bytes of JSON
+const MAX_MESSAGE_SIZE: usize = 1024;
+const MAX_ADDRESS_LENGTH: usize = 64;
+// How often a guest asks to join, or says it's still there once it's in, and how often the
+// host answers with the lobby
+const HEARTBEAT_SECS: f32 = 1.;
+// A racer or peer not heard from for this long is counted out
+const TIMEOUT_SECS: f32 = 5.;
+
+const TITLE_FONT_SIZE: f32 = 30.0;
+const ROW_FONT_SIZE: f32 = 18.0;
+const TEXT_COLOR: Color = Color::srgb(0.1, 0., 0.);
+const PANEL_COLOR: Color = Color::srgba(1., 1., 1., 0.8);
+const SELECTED_ROW_COLOR: Color = Color::srgb(1.0, 0.7, 0.1);
+const EDITING_ROW_COLOR: Color = Color::srgb(1.0, 0.45, 0.3);
+
+/// Races other instances on the local network. One hosts, the others join it by address,
+/// and everyone flies the host's course while the birds of the others are drawn alongside.
+/// Guests only talk to the host, which passes on what they send to everyone else.
+pub struct LanPlugin;
+
+impl Plugin for LanPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<Received>()
+            .init_resource::<LanLobby>()
+            .init_resource::<Racers>()
+            .add_systems(OnEnter(GameState::LanLobby), lobby_setup)
+            .add_systems(
+                OnExit(GameState::LanLobby),
+                (despawn_screen::<OnLanLobbyScreen>, save_address),
+            )
+            .add_systems(OnEnter(GameState::Menu), leave_session)
+            .add_systems(
+                PreUpdate,
+                (keep_in_touch, receive)
+                    .chain()
+                    .run_if(resource_exists::<LanSession>),
+            )
+            .add_systems(
+                Update,
+                (lobby_input, update_lobby)
+                    .chain()
+                    .run_if(in_state(GameState::LanLobby)),
+            )
+            .add_systems(
+                Update,
+                start_next_race.run_if(
+                    in_state(GameState::LanLobby)
+                        .or(in_state(GameState::DeathScreen).and(racing))
+                        .and(resource_exists::<LanSession>),
+                ),
+            )
+            .add_systems(Update, (start_races, update_racers).chain())
+            // Once the tick is settled, so the others see where the bird really ended up
+            .add_systems(
+                FixedUpdate,
+                (send_flight, send_crash.run_if(on_event::<DeathEvent>))
+                    .after(PhysicsSet::Collide)
+                    .run_if(
+                        in_state(PlayState::Running)
+                            .and(racing)
+                            .and(resource_exists::<LanSession>),
+                    ),
+            )
+            .add_systems(
+                Update,
+                (tint_own_bird, fly_remote_birds).run_if(
+                    in_state(GameState::Game)
+                        .and(racing)
+                        .and(resource_exists::<LanSession>),
+                ),
+            )
+            .add_systems(OnExit(GameState::Game), despawn_screen::<RemoteBird>)
+            .add_systems(
+                OnEnter(GameState::DeathScreen),
+                results_setup.run_if(racing.and(resource_exists::<LanSession>)),
+            )
+            .add_systems(
+                OnExit(GameState::DeathScreen),
+                despawn_screen::<OnRaceResults>,
+            )
+            .add_systems(
+                Update,
+                (leave_race, update_results).run_if(
+                    in_state(GameState::DeathScreen)
+                        .and(racing)
+                        .and(resource_exists::<LanSession>),
+                ),
+            );
+    }
+}
+
+/// Everything that goes over the network, one JSON object per datagram.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Message {
+    /// A guest asking the host for a place in the race.
+    Join,
+    /// The host handing a guest its player number.
+    Welcome { player: usize },
+    /// The host turning a guest away, every place is taken.
+    Full,
+    /// How many players are in, sent by the host whenever that changes.
+    Lobby { players: usize },
+    /// The host starting a race, everyone flies the same course.
+    Start { seed: u64, preset: Preset },
+    /// Where a bird is on a tick of the race with this seed.
+    Flight {
+        player: usize,
+        seed: u64,
+        tick: u64,
+        y: f32,
+        rotation: f32,
+        score: usize,
+    },
+    /// A bird crashing on a tick of the race with this seed.
+    Crashed {
+        player: usize,
+        seed: u64,
+        tick: u64,
+        score: usize,
+    },
+    /// A player going back to the menu. When it's the host, the race is over for everyone.
+    Leave { player: usize },
+}
+
+impl Message {
+    /// The player number the message is about, if it's about one.
+    fn player(&self) -> Option<usize> {
+        match *self {
+            Message::Welcome { player }
+            | Message::Flight { player, .. }
+            | Message::Crashed { player, .. }
+            | Message::Leave { player } => Some(player),
+            Message::Join | Message::Full | Message::Lobby { .. } | Message::Start { .. } => None,
+        }
+    }
+
+    /// The same message about the given player, for what a guest says about itself.
+    fn sent_by(mut self, sender: usize) -> Self {
+        match &mut self {
+            Message::Flight { player, .. }
+            | Message::Crashed { player, .. }
+            | Message::Leave { player } => *player = sender,
+            Message::Join
+            | Message::Welcome { .. }
+            | Message::Full
+            | Message::Lobby { .. }
+            | Message::Start { .. } => {}
+        }
+        self
+    }
+}
+
+/// A message the gameplay has to act on, from another instance or the host itself.
+#[derive(Event)]
+struct Received(Message);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Role {
+    Host,
+    Guest,
+}
+
+struct Peer {
+    address: SocketAddr,
+    player: usize,
+    /// Seconds since anything was heard from it.
+    silent_secs: f32,
+    /// Seed of the last race it's known to have, from its flights or because it joined after
+    /// the start. The host sends the start again until it's the current one.
+    started: Option<u64>,
+}
+
+impl Peer {
+    fn new(address: SocketAddr, player: usize) -> Self {
+        Self {
+            address,
+            player,
+            silent_secs: 0.,
+            started: None,
+        }
+    }
+}
+
+/// Present while hosting or joined, dropped on the way back to the menu.
+#[derive(Resource)]
+pub struct LanSession {
+    socket: UdpSocket,
+    role: Role,
+    /// This instance's player number, the host is always 0. None until a guest is welcomed.
+    player: Option<usize>,
+    /// For the host every guest, for a guest only the host.
+    peers: Vec<Peer>,
+    players: usize,
+    heartbeat: Timer,
+    /// The race the host started last, sent again to guests that aren't flying it yet.
+    race: Option<(u64, Preset)>,
+    /// Set once the host said there's no room.
+    turned_away: bool,
+}
+
+impl LanSession {
+    fn host() -> io::Result<Self> {
+        Self::bind(("0.0.0.0", PORT), Role::Host, Vec::new())
+    }
+
+    fn join(address: &str) -> io::Result<Self> {
+        let host = resolve(address)?;
+        let peers = vec![Peer::new(host, 0)];
+        let session = Self::bind(("0.0.0.0", 0), Role::Guest, peers)?;
+        session.send_all(&Message::Join, None);
+        Ok(session)
+    }
+
+    fn bind(address: impl ToSocketAddrs, role: Role, peers: Vec<Peer>) -> io::Result<Self> {
+        let socket = UdpSocket::bind(address)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            role,
+            player: (role == Role::Host).then_some(0),
+            peers,
+            players: 1,
+            heartbeat: Timer::from_seconds(HEARTBEAT_SECS, TimerMode::Repeating),
+            race: None,
+            turned_away: false,
+        })
+    }
+
+    fn send_to(&self, message: &Message, address: SocketAddr) {
+        let bytes = match serde_json::to_vec(message) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                error!("Failed to encode {:?}: {}", message, e);
+                return;
+            }
+        };
+        // Nothing waits for an answer, a lost datagram is as good as a failed send
+        if let Err(e) = self.socket.send_to(&bytes, address) {
+            warn!("Failed to send to {}: {}", address, e);
+        }
+    }
+
+    /// Forgets a guest and tells everyone else it's gone.
+    fn remove_peer(&mut self, player: usize) {
+        self.peers.retain(|peer| peer.player != player);
+        self.players = self.peers.len() + 1;
+        self.send_all(&Message::Leave { player }, None);
+        self.send_all(
+            &Message::Lobby {
+                players: self.players,
+            },
+            None,
+        );
+    }
+
+    /// Sends to every peer but the one at `except`, if any.
+    fn send_all(&self, message: &Message, except: Option<SocketAddr>) {
+        for peer in &self.peers {
+            if Some(peer.address) != except {
+                self.send_to(message, peer.address);
+            }
+        }
+    }
+}
+
+// The port can be left off, it's always the same one
+fn resolve(address: &str) -> io::Result<SocketAddr> {
+    let address = address.trim();
+    let with_port = if address.contains(':') {
+        address.to_string()
+    } else {
+        format!("{}:{}", address, PORT)
+    };
+    with_port
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address found for that name"))
+}
+
+/// What the other players in the race are doing, by player number.
+#[derive(Resource, Default)]
+struct Racers(BTreeMap<usize, Racer>);
+
+#[derive(Default)]
+struct Racer {
+    /// Positions not shown yet, oldest first, so the bird can be drawn where it was at the
+    /// same point of the course as the local one.
+    track: VecDeque<FlightSample>,
+    score: usize,
+    crashed_at: Option<u64>,
+    /// Seconds since anything was heard from it.
+    silent_secs: f32,
+}
+
+struct FlightSample {
+    tick: u64,
+    y: f32,
+    rotation: f32,
+}
+
+impl Racer {
+    fn out(&self) -> bool {
+        self.crashed_at.is_some() || self.silent_secs > TIMEOUT_SECS
+    }
+}
+
+#[derive(Resource, Default)]
+struct LanLobby {
+    index: usize,
+    /// The address to join, saved in the settings on the way out.
+    address: String,
+    /// Typing the address to join.
+    editing: bool,
+    /// The last thing that went wrong, shown until the next try.
+    error: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LobbyRow {
+    Host,
+    Join,
+}
+
+const LOBBY_ROWS: [LobbyRow; 2] = [LobbyRow::Host, LobbyRow::Join];
+
+#[derive(Component)]
+struct OnLanLobbyScreen;
+
+#[derive(Component)]
+struct LanLobbyRow(usize);
+
+/// The lines under the rows, how joining or hosting goes and what to press.
+#[derive(Component)]
+enum LanLobbyLine {
+    Status,
+    Hint,
+}
+
+/// Another player's bird, only drawn, it never collides or scores here.
+#[derive(Component)]
+struct RemoteBird(usize);
+
+#[derive(Component)]
+struct OnRaceResults;
+
+#[derive(Component)]
+struct RaceResultsRow(usize);
+
+#[derive(Component)]
+struct RaceResultsHint;
+
+fn lobby_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut lobby: ResMut<LanLobby>,
+    settings: Res<Settings>,
+) {
+    *lobby = LanLobby {
+        address: settings.lan_address.clone(),
+        ..default()
+    };
+    let font = asset_server.load("embedded://flappyboi/../assets/fonts/FiraSans-Bold.ttf");
+    let row_font = TextFont {
+        font: font.clone(),
+        font_size: ROW_FONT_SIZE,
+        ..default()
+    };
+
+    commands
+        .spawn((
+            OnLanLobbyScreen,
+            Node {
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                height: Val::Percent(100.),
+                width: Val::Percent(100.),
+                padding: UiRect::all(Val::Px(10.)),
+                row_gap: Val::Px(6.),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new("LAN Race"),
+                TextColor(TEXT_COLOR),
+                TextFont {
+                    font: font.clone(),
+                    font_size: TITLE_FONT_SIZE,
+                    ..default()
+                },
+            ));
+
+            parent
+                .spawn((
+                    Node {
+                        flex_direction: FlexDirection::Column,
+                        padding: UiRect::all(Val::Px(6.)),
+                        row_gap: Val::Px(2.),
+                        ..default()
+                    },
+                    BackgroundColor(PANEL_COLOR),
+                ))
+                .with_children(|table| {
+                    for index in 0..LOBBY_ROWS.len() {
+                        table.spawn((
+                            LanLobbyRow(index),
+                            Text::default(),
+                            TextColor(TEXT_COLOR),
+                            row_font.clone(),
+                            Node {
+                                width: Val::Px(300.),
+                                padding: UiRect::axes(Val::Px(4.), Val::Px(2.)),
+                                ..default()
+                            },
+                            BackgroundColor(Color::NONE),
+                        ));
+                    }
+                });
+
+            parent.spawn((
+                LanLobbyLine::Status,
+                Text::default(),
+                TextColor(TEXT_COLOR),
+                row_font.clone(),
+            ));
+            parent.spawn((
+                LanLobbyLine::Hint,
+                Text::default(),
+                TextColor(TEXT_COLOR),
+                row_font,
+            ));
+        });
+}
+
+fn lobby_input(
+    mut commands: Commands,
+    input: ActionInput,
+    mut keyboard_events: EventReader<KeyboardInput>,
+    mut lobby: ResMut<LanLobby>,
+    session: Option<Res<LanSession>>,
+) {
+    if lobby.editing {
+        for event in keyboard_events.read() {
+            if event.state != ButtonState::Pressed {
+                continue;
+            }
+            match &event.logical_key {
+                Key::Enter => {
+                    lobby.editing = false;
+                    lobby.error = None;
+                    match LanSession::join(&lobby.address) {
+                        Ok(session) => {
+                            info!("Joining the race at {}", lobby.address);
+                            commands.insert_resource(session);
+                        }
+                        Err(e) => lobby.error = Some(format!("Couldn't join: {}", e)),
+                    }
+                }
+                Key::Escape => lobby.editing = false,
+                Key::Backspace => {
+                    lobby.address.pop();
+                }
+                Key::Character(characters) => {
+                    for character in characters.chars() {
+                        let allowed = character.is_ascii_alphanumeric()
+                            || matches!(character, '.' | ':' | '-');
+                        if allowed && lobby.address.len() < MAX_ADDRESS_LENGTH {
+                            lobby.address.push(character);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        return;
+    }
+    keyboard_events.clear();
+
+    // Back from the lobby leaves the race too, that happens on the way into the menu
+    if input.just_pressed(Action::Back) {
+        commands.set_state(GameState::Menu);
+        return;
+    }
+
+    // Starting is up to `start_next_race` from here on
+    if session.is_some() {
+        return;
+    }
+
+    if input.just_pressed(Action::Down) || input.just_pressed(Action::Up) {
+        lobby.index = (lobby.index + 1) % LOBBY_ROWS.len();
+    } else if input.just_pressed(Action::Confirm) {
+        lobby.error = None;
+        match LOBBY_ROWS[lobby.index] {
+            LobbyRow::Host => match LanSession::host() {
+                Ok(session) => {
+                    info!("Hosting a race on port {}", PORT);
+                    commands.insert_resource(session);
+                }
+                Err(e) => lobby.error = Some(format!("Couldn't host: {}", e)),
+            },
+            LobbyRow::Join => lobby.editing = true,
+        }
+    }
+}
+
+fn update_lobby(
+    lobby: Res<LanLobby>,
+    settings: Res<Settings>,
+    session: Option<Res<LanSession>>,
+    mut row_q: Query<(&LanLobbyRow, &mut Text, &mut BackgroundColor)>,
+    mut line_q: Query<(&LanLobbyLine, &mut Text), Without<LanLobbyRow>>,
+) {
+    for (row, mut text, mut background) in row_q.iter_mut() {
+        text.0 = match LOBBY_ROWS[row.0] {
+            LobbyRow::Host => format!("Host on port {}", PORT),
+            LobbyRow::Join => format!("Join {}", lobby.address),
+        };
+        // Picking happens before there's a session
+        background.0 = match (row.0 == lobby.index && session.is_none(), lobby.editing) {
+            (true, true) => EDITING_ROW_COLOR,
+            (true, false) => SELECTED_ROW_COLOR,
+            (false, _) => Color::NONE,
+        };
+    }
+
+    let status = match (&session, &lobby.error) {
+        (_, Some(error)) => error.clone(),
+        (None, None) => String::new(),
+        (Some(session), None) => match (session.role, session.player) {
+            (_, None) if session.turned_away => "That race is full".to_string(),
+            (_, None) => "Waiting for the host to answer".to_string(),
+            (Role::Host, Some(_)) => format!("Hosting, {} players in", session.players),
+            (Role::Guest, Some(player)) => format!(
+                "Joined as player {}, {} players in",
+                player + 1,
+                session.players
+            ),
+        },
+    };
+
+    let back = settings.bindings.describe(Action::Back);
+    let confirm = settings.bindings.describe(Action::Confirm);
+    let hint = match &session {
+        _ if lobby.editing => "Type the host's address, Enter to join".to_string(),
+        None => format!("{} to pick, {} to go back", confirm, back),
+        Some(session) if session.role == Role::Host && session.players > 1 => {
+            format!("{} to start, {} to leave", confirm, back)
+        }
+        Some(session) if session.role == Role::Host => {
+            format!("Waiting for players, {} to leave", back)
+        }
+        Some(_) => format!("The host starts the race, {} to leave", back),
+    };
+    for (line, mut text) in line_q.iter_mut() {
+        text.0.clone_from(match line {
+            LanLobbyLine::Status => &status,
+            LanLobbyLine::Hint => &hint,
+        });
+    }
+}
+
+fn save_address(
+    lobby: Res<LanLobby>,
+    mut settings: ResMut<Settings>,
+    mut save_requests: EventWriter<SaveRequested>,
+) {
+    settings.lan_address.clone_from(&lobby.address);
+    save_requests.send_default();
+}
+
+fn leave_session(
+    mut commands: Commands,
+    session: Option<Res<LanSession>>,
+    mut racers: ResMut<Racers>,
+) {
+    let Some(session) = session else {
+        return;
+    };
+    if let Some(player) = session.player {
+        session.send_all(&Message::Leave { player }, None);
+    }
+    racers.0.clear();
+    commands.remove_resource::<LanSession>();
+}
+
+// A guest that can't hear the host any more is out of the race, the host carries on without
+// a guest that went quiet
+fn keep_in_touch(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut session: ResMut<LanSession>,
+    mut received: EventWriter<Received>,
+) {
+    for peer in session.peers.iter_mut() {
+        peer.silent_secs += time.delta_secs();
+    }
+    let silent: Vec<usize> = session
+        .peers
+        .iter()
+        .filter(|peer| peer.silent_secs > TIMEOUT_SECS)
+        .map(|peer| peer.player)
+        .collect();
+    match session.role {
+        // Before the welcome there's only the "waiting" message to go by
+        Role::Guest if session.player.is_some() && !silent.is_empty() => {
+            warn!("Lost touch with the host, the race is over");
+            commands.remove_resource::<LanSession>();
+            commands.set_state(GameState::Menu);
+            return;
+        }
+        Role::Guest => {}
+        Role::Host => {
+            for player in silent {
+                info!("Player {} went quiet and was dropped", player + 1);
+                session.remove_peer(player);
+                received.send(Received(Message::Leave { player }));
+            }
+        }
+    }
+
+    if !session.heartbeat.tick(time.delta()).just_finished() {
+        return;
+    }
+    match session.role {
+        Role::Guest if !session.turned_away => session.send_all(&Message::Join, None),
+        Role::Guest => {}
+        Role::Host => {
+            let lobby = Message::Lobby {
+                players: session.players,
+            };
+            session.send_all(&lobby, None);
+            // The start is a single datagram, whoever missed it gets it again
+            if let Some((seed, preset)) = session.race {
+                let start = Message::Start { seed, preset };
+                for peer in &session.peers {
+                    if peer.started != Some(seed) {
+                        session.send_to(&start, peer.address);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Takes care of joining and leaving right here, since that only concerns the session. The
+// host passes everything its guests say on to the others.
+fn receive(
+    mut commands: Commands,
+    mut session: ResMut<LanSession>,
+    mut received: EventWriter<Received>,
+) {
+    let mut buffer = [0; MAX_MESSAGE_SIZE];
+    loop {
+        let (length, from) = match session.socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+            // A guest that went away without a word makes the next read fail on some systems
+            Err(e) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+            Err(e) => {
+                warn!("Failed to receive: {}", e);
+                return;
+            }
+        };
+        let message: Message = match serde_json::from_slice(&buffer[..length]) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("Ignoring a message from {} that doesn't parse: {}", from, e);
+                continue;
+            }
+        };
+        // Player numbers pick the tints, there are only so many
+        if message.player().is_some_and(|player| player >= MAX_PLAYERS) {
+            warn!("Ignoring {:?} from {}, no such player", message, from);
+            continue;
+        }
+        // Guests only listen to the host, and the host only to guests that joined, each of
+        // which speaks for the player it joined as and nobody else
+        let sender = session
+            .peers
+            .iter_mut()
+            .find(|peer| peer.address == from)
+            .map(|peer| {
+                peer.silent_secs = 0.;
+                if let Message::Flight { seed, .. } | Message::Crashed { seed, .. } = message {
+                    peer.started = Some(seed);
+                }
+                peer.player
+            });
+        let message = match (session.role, sender, message) {
+            (Role::Host, None, Message::Join) => Message::Join,
+            (Role::Host, Some(sender), message) => message.sent_by(sender),
+            // The host passes on what the others say, about themselves
+            (Role::Guest, Some(_), message) => message,
+            (_, None, message) => {
+                warn!("Ignoring {:?} from {}, not in this race", message, from);
+                continue;
+            }
+        };
+
+        match (session.role, message) {
+            (Role::Host, Message::Join) => {
+                let known = session.peers.iter().find(|peer| peer.address == from);
+                let player = match known {
+                    Some(peer) => peer.player,
+                    None => {
+                        let Some(player) = (1..MAX_PLAYERS)
+                            .find(|&player| session.peers.iter().all(|peer| peer.player != player))
+                        else {
+                            session.send_to(&Message::Full, from);
+                            continue;
+                        };
+                        info!("Player {} joined from {}", player + 1, from);
+                        // A race already going on is left to the ones flying it
+                        let mut peer = Peer::new(from, player);
+                        peer.started = session.race.map(|(seed, _)| seed);
+                        session.peers.push(peer);
+                        session.players = session.peers.len() + 1;
+                        let lobby = Message::Lobby {
+                            players: session.players,
+                        };
+                        session.send_all(&lobby, None);
+                        player
+                    }
+                };
+                // Joined guests keep asking, as a sign they're still there
+                session.send_to(&Message::Welcome { player }, from);
+            }
+            (Role::Host, Message::Leave { player }) => {
+                info!("Player {} left", player + 1);
+                session.remove_peer(player);
+                received.send(Received(Message::Leave { player }));
+            }
+            (Role::Host, message @ (Message::Flight { .. } | Message::Crashed { .. })) => {
+                session.send_all(&message, Some(from));
+                received.send(Received(message));
+            }
+            (Role::Guest, Message::Welcome { player }) => {
+                if session.player.is_none() {
+                    info!("Joined the race as player {}", player + 1);
+                }
+                session.player = Some(player.min(MAX_PLAYERS - 1));
+            }
+            (Role::Guest, Message::Full) => {
+                warn!("The race at {} is full", from);
+                session.turned_away = true;
+            }
+            (Role::Guest, Message::Lobby { players }) => session.players = players,
+            (Role::Guest, Message::Leave { player: 0 }) => {
+                warn!("The host left, the race is over");
+                commands.remove_resource::<LanSession>();
+                commands.set_state(GameState::Menu);
+                return;
+            }
+            (
+                Role::Guest,
+                message @ (Message::Start { .. }
+                | Message::Flight { .. }
+                | Message::Crashed { .. }
+                | Message::Leave { .. }),
+            ) => {
+                received.send(Received(message));
+            }
+            (role, message) => {
+                warn!(
+                    "Ignoring {:?} from {}, not expected as {:?}",
+                    message, from, role
+                );
+            }
+        }
+    }
+}
+
+// A new race can come in while still flying the last one, it starts over right away
+fn start_races(
+    mut commands: Commands,
+    mut received: EventReader<Received>,
+    mut mode: ResMut<GameMode>,
+    mut racers: ResMut<Racers>,
+    game_state: Res<State<GameState>>,
+    mut next_game_state: ResMut<NextState<GameState>>,
+    mut next_play_state: ResMut<NextState<PlayState>>,
+) {
+    for Received(message) in received.read() {
+        let Message::Start { seed, preset } = *message else {
+            continue;
+        };
+        // The host repeats the start until it hears from everyone flying it
+        if *mode == (GameMode::Race { seed, preset }) {
+            continue;
+        }
+        info!("Starting a race on {} with seed {}", preset.name(), seed);
+        *mode = GameMode::Race { seed, preset };
+        racers.0.clear();
+        if *game_state.get() == GameState::Game {
+            next_play_state.set(PlayState::GetReady);
+            commands.queue(restart_run);
+        } else {
+            next_game_state.set(GameState::Game);
+        }
+    }
+}
+
+fn update_racers(
+    time: Res<Time>,
+    mode: Res<GameMode>,
+    mut received: EventReader<Received>,
+    mut racers: ResMut<Racers>,
+) {
+    for racer in racers.0.values_mut() {
+        racer.silent_secs += time.delta_secs();
+    }
+
+    // Anything from an earlier race is still on its way, and left out
+    let GameMode::Race { seed: race, .. } = *mode else {
+        received.clear();
+        return;
+    };
+    for Received(message) in received.read() {
+        match *message {
+            Message::Flight {
+                player,
+                seed,
+                tick,
+                y,
+                rotation,
+                score,
+            } if seed == race => {
+                let racer = racers.0.entry(player).or_default();
+                racer.track.push_back(FlightSample { tick, y, rotation });
+                racer.score = racer.score.max(score);
+                racer.silent_secs = 0.;
+            }
+            Message::Crashed {
+                player,
+                seed,
+                tick,
+                score,
+            } if seed == race => {
+                let racer = racers.0.entry(player).or_default();
+                racer.crashed_at = Some(tick);
+                racer.score = score;
+                racer.silent_secs = 0.;
+            }
+            Message::Leave { player } => {
+                racers.0.remove(&player);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn send_flight(
+    session: Res<LanSession>,
+    mode: Res<GameMode>,
+    run: Res<CurrentRun>,
+    score: Res<Score>,
+    bird_q: Query<(&Position, &Transform), With<Player>>,
+) {
+    let (Some(player), GameMode::Race { seed, .. }, Ok((position, transform))) =
+        (session.player, *mode, bird_q.get_single())
+    else {
+        return;
+    };
+    let flight = Message::Flight {
+        player,
+        seed,
+        tick: run.ticks,
+        y: position.current.y,
+        rotation: transform.rotation.to_euler(EulerRot::ZYX).0,
+        score: **score,
+    };
+    session.send_all(&flight, None);
+}
+
+fn send_crash(
+    session: Res<LanSession>,
+    mode: Res<GameMode>,
+    run: Res<CurrentRun>,
+    score: Res<Score>,
+    mut death_events: EventReader<DeathEvent>,
+    player_q: Query<(), With<Player>>,
+) {
+    let (Some(player), GameMode::Race { seed, .. }) = (session.player, *mode) else {
+        death_events.clear();
+        return;
+    };
+    for event in death_events.read() {
+        if player_q.contains(event.bird) {
+            let crashed = Message::Crashed {
+                player,
+                seed,
+                tick: run.ticks,
+                score: **score,
+            };
+            session.send_all(&crashed, None);
+        }
+    }
+}
+
+fn tint_own_bird(session: Res<LanSession>, mut bird_q: Query<&mut Sprite, Added<Player>>) {
+    for mut sprite in bird_q.iter_mut() {
+        sprite.color = TINTS[session.player.unwrap_or(0)];
+    }
+}
+
+// Every other bird is drawn where it was when it had come as far as the local one, like the
+// ghost. Behind that it shows where it is now.
+fn fly_remote_birds(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    run: Res<CurrentRun>,
+    play_state: Res<State<PlayState>>,
+    mut racers: ResMut<Racers>,
+    mut remote_q: Query<(Entity, &RemoteBird, &mut Transform, &mut Visibility)>,
+) {
+    for (entity, remote, mut transform, mut visibility) in remote_q.iter_mut() {
+        let Some(racer) = racers.0.get_mut(&remote.0) else {
+            commands.entity(entity).despawn();
+            continue;
+        };
+        while racer.track.len() > 1 && racer.track[1].tick <= run.ticks {
+            racer.track.pop_front();
+        }
+
+        let gone = racer.crashed_at.is_some_and(|tick| tick < run.ticks);
+        let shown = *play_state.get() != PlayState::GetReady && !gone;
+        match racer.track.front().filter(|_| shown) {
+            Some(sample) => {
+                transform.translation.y = sample.y;
+                transform.rotation = Quat::from_rotation_z(sample.rotation);
+                visibility.set_if_neq(Visibility::Inherited);
+            }
+            None => {
+                visibility.set_if_neq(Visibility::Hidden);
+            }
+        }
+    }
+
+    for &player in racers.0.keys() {
+        if remote_q.iter().any(|(_, remote, ..)| remote.0 == player) {
+            continue;
+        }
+        commands.spawn((
+            RemoteBird(player),
+            Sprite {
+                image: asset_server.load("embedded://flappyboi/../assets/bird.png"),
+                color: TINTS[player],
+                ..default()
+            },
+            // Just behind the local bird
+            Transform::from_xyz(BIRD_X, 0., 4.),
+            Visibility::Hidden,
+        ));
+    }
+}
+
+fn results_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font = asset_server.load("embedded://flappyboi/../assets/fonts/FiraSans-Bold.ttf");
+    let row_font = TextFont {
+        font: font.clone(),
+        font_size: ROW_FONT_SIZE,
+        ..default()
+    };
+
+    commands
+        .spawn((
+            OnRaceResults,
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.),
+                top: Val::Px(110.),
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn((
+                    Node {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        padding: UiRect::all(Val::Px(10.)),
+                        row_gap: Val::Px(4.),
+                        ..default()
+                    },
+                    BackgroundColor(PANEL_COLOR),
+                    BorderRadius::all(Val::Px(8.)),
+                ))
+                .with_children(|panel| {
+                    panel.spawn((
+                        Text::new("Race"),
+                        TextColor(TEXT_COLOR),
+                        TextFont {
+                            font: font.clone(),
+                            font_size: TITLE_FONT_SIZE,
+                            ..default()
+                        },
+                    ));
+                    // Filled in by `update_results`, the others may still be flying
+                    for index in 0..MAX_PLAYERS {
+                        panel.spawn((
+                            RaceResultsRow(index),
+                            Text::default(),
+                            TextColor(TEXT_COLOR),
+                            row_font.clone(),
+                        ));
+                    }
+                    panel.spawn((
+                        RaceResultsHint,
+                        Text::default(),
+                        TextColor(TEXT_COLOR),
+                        row_font,
+                    ));
+                });
+        });
+}
+
+// Only the host starts races, from the lobby or after the last one. The guests come along.
+fn start_next_race(
+    input: ActionInput,
+    mut session: ResMut<LanSession>,
+    lobby: Res<LanLobby>,
+    preset: Res<Preset>,
+    mut received: EventWriter<Received>,
+) {
+    let confirmed = input.just_pressed(Action::Confirm) || input.pointer_just_pressed();
+    if session.role != Role::Host || session.players < 2 || lobby.editing || !confirmed {
+        return;
+    }
+    // Random seeds are kept small enough to read out and type back in
+    let seed = rand::thread_rng().gen_range(0..1_000_000_000);
+    let start = Message::Start {
+        seed,
+        preset: *preset,
+    };
+    session.race = Some((seed, *preset));
+    session.send_all(&start, None);
+    received.send(Received(start));
+}
+
+fn leave_race(input: ActionInput, mut game_state: ResMut<NextState<GameState>>) {
+    if input.just_pressed(Action::Back) {
+        game_state.set(GameState::Menu);
+    }
+}
+
+fn update_results(
+    session: Res<LanSession>,
+    settings: Res<Settings>,
+    score: Res<Score>,
+    racers: Res<Racers>,
+    mut row_q: Query<(&RaceResultsRow, &mut Text, &mut TextColor, &mut Node)>,
+    mut hint_q: Query<&mut Text, (With<RaceResultsHint>, Without<RaceResultsRow>)>,
+) {
+    // The local bird is down, that's how the death screen came up
+    let me = session.player.unwrap_or(0);
+    let mut standings: Vec<(usize, usize, bool)> = racers
+        .0
+        .iter()
+        .map(|(&player, racer)| (player, racer.score, racer.out()))
+        .chain(std::iter::once((me, **score, true)))
+        .collect();
+    standings.sort_by_key(|&(player, score, _)| (std::cmp::Reverse(score), player));
+
+    for (row, mut text, mut color, mut node) in row_q.iter_mut() {
+        let Some(&(player, score, out)) = standings.get(row.0) else {
+            node.display = Display::None;
+            continue;
+        };
+        node.display = Display::Flex;
+        color.0 = TEXT_TINTS[player];
+        let you = if player == me { " (you)" } else { "" };
+        let state = if out { "" } else { ", still flying" };
+        text.0 = format!("Player {}{}: {}{}", player + 1, you, score, state);
+    }
+
+    let back = settings.bindings.describe(Action::Back);
+    let hint = match session.role {
+        Role::Host => format!(
+            "{} to race again, {} to leave",
+            settings.bindings.describe(Action::Confirm),
+            back
+        ),
+        Role::Guest => format!("Waiting for the host, {} to leave", back),
+    };
+    for mut text in hint_q.iter_mut() {
+        text.0.clone_from(&hint);
+    }
+}
@@ -0,0 +1,452 @@
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{despawn_screen, GameState, Score};
+use crate::difficulty::Preset;
+use crate::game::{CurrentRun, GameMode};
+use crate::input::{Action, ActionInput};
+use crate::persistence::SaveRequested;
+use crate::settings::Settings;
+use bevy::input::keyboard::{Key, KeyboardInput};
+use bevy::input::ButtonState;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub const LEADERBOARD_SIZE: usize = 10;
+const MAX_NAME_LENGTH: usize = 12;
+const DEFAULT_PLAYER_NAME: &str = "Player";
+
+const TITLE_FONT_SIZE: f32 = 30.0;
+const ROW_FONT_SIZE: f32 = 16.0;
+const TEXT_COLOR: Color = Color::srgb(0.1, 0., 0.);
+const HIGHLIGHT_COLOR: Color = Color::srgb(1.0, 0.7, 0.1);
+const PANEL_COLOR: Color = Color::srgba(1., 1., 1., 0.8);
+
+pub struct LeaderboardPlugin;
+
+impl Plugin for LeaderboardPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            OnEnter(GameState::DeathScreen),
+            (
+                // Daily challenge runs are kept in their own results instead
+                start_name_entry.run_if(resource_equals(GameMode::Classic)),
+                name_entry_setup.run_if(resource_exists::<NameEntry>),
+            )
+                .chain(),
+        )
+        .add_systems(
+            OnExit(GameState::DeathScreen),
+            despawn_screen::<OnNameEntryScreen>,
+        )
+        .add_systems(
+            Update,
+            (type_name, update_name_entry_text)
+                .chain()
+                .run_if(in_state(GameState::DeathScreen).and(resource_exists::<NameEntry>)),
+        )
+        .add_systems(OnEnter(GameState::Leaderboard), leaderboard_setup)
+        .add_systems(
+            OnExit(GameState::Leaderboard),
+            despawn_screen::<OnLeaderboardScreen>,
+        )
+        .add_systems(
+            Update,
+            close_leaderboard.run_if(in_state(GameState::Leaderboard)),
+        );
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LeaderboardEntry {
+    pub name: String,
+    pub score: usize,
+    /// Seconds since the unix epoch when the run was entered.
+    pub timestamp: u64,
+    pub duration_secs: f32,
+    pub seed: Option<u64>,
+}
+
+/// The best runs ever, best first.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct Leaderboard {
+    pub entries: Vec<LeaderboardEntry>,
+}
+
+static EMPTY_LEADERBOARD: Leaderboard = Leaderboard {
+    entries: Vec::new(),
+};
+
+/// A separate leaderboard for every difficulty preset.
+#[derive(Resource, Default, Clone, Debug)]
+pub struct Leaderboards(pub BTreeMap<Preset, Leaderboard>);
+
+impl Leaderboards {
+    pub fn get(&self, preset: Preset) -> &Leaderboard {
+        self.0.get(&preset).unwrap_or(&EMPTY_LEADERBOARD)
+    }
+
+    pub fn get_mut(&mut self, preset: Preset) -> &mut Leaderboard {
+        self.0.entry(preset).or_default()
+    }
+}
+
+impl Leaderboard {
+    pub fn qualifies(&self, score: usize) -> bool {
+        if score == 0 {
+            return false;
+        }
+
+        self.entries.len() < LEADERBOARD_SIZE
+            || self.entries.last().is_some_and(|last| score > last.score)
+    }
+
+    /// Inserts the entry in its place and returns its index, ties go to the older run.
+    pub fn insert(&mut self, entry: LeaderboardEntry) -> usize {
+        let index = self
+            .entries
+            .iter()
+            .position(|existing| entry.score > existing.score)
+            .unwrap_or(self.entries.len());
+
+        self.entries.insert(index, entry);
+        self.entries.truncate(LEADERBOARD_SIZE);
+        index
+    }
+}
+
+/// Present while the player is typing a name for a run that made the leaderboard.
+#[derive(Resource)]
+pub struct NameEntry {
+    name: String,
+    preset: Preset,
+    score: usize,
+    duration_secs: f32,
+    seed: Option<u64>,
+}
+
+#[derive(Component)]
+pub struct OnNameEntryScreen;
+
+#[derive(Component)]
+struct NameEntryText;
+
+#[derive(Component)]
+struct OnLeaderboardScreen;
+
+fn start_name_entry(
+    mut commands: Commands,
+    score: Res<Score>,
+    leaderboards: Res<Leaderboards>,
+    preset: Res<Preset>,
+    settings: Res<Settings>,
+    run: Res<CurrentRun>,
+) {
+    if !leaderboards.get(*preset).qualifies(**score) {
+        return;
+    }
+
+    commands.insert_resource(NameEntry {
+        name: settings.player_name.clone(),
+        preset: *preset,
+        score: **score,
+        duration_secs: run.duration.elapsed_secs(),
+        seed: run.seed,
+    });
+}
+
+fn name_entry_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font = asset_server.load("embedded://flappyboi/../assets/fonts/FiraSans-Bold.ttf");
+
+    commands
+        .spawn((
+            OnNameEntryScreen,
+            // Sits where the retry prompt would be, which is hidden while typing
+            Node {
+                align_items: AlignItems::End,
+                justify_content: JustifyContent::Center,
+                height: Val::Percent(100.),
+                width: Val::Percent(100.),
+                bottom: Val::Percent(5.),
+                position_type: PositionType::Absolute,
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn((
+                    Node {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        padding: UiRect::all(Val::Px(10.)),
+                        row_gap: Val::Px(5.),
+                        ..default()
+                    },
+                    BackgroundColor(PANEL_COLOR),
+                ))
+                .with_children(|panel| {
+                    panel.spawn((
+                        Text::new("New top 10 score!"),
+                        TextColor(TEXT_COLOR),
+                        TextFont {
+                            font: font.clone(),
+                            font_size: 24.0,
+                            ..default()
+                        },
+                    ));
+                    panel.spawn((
+                        Text::new("Type your name, enter or tap to save"),
+                        TextColor(TEXT_COLOR),
+                        TextFont {
+                            font: font.clone(),
+                            font_size: ROW_FONT_SIZE,
+                            ..default()
+                        },
+                    ));
+                    panel.spawn((
+                        NameEntryText,
+                        Text::default(),
+                        TextColor(HIGHLIGHT_COLOR),
+                        TextFont {
+                            font: font.clone(),
+                            font_size: 24.0,
+                            ..default()
+                        },
+                    ));
+                });
+        });
+}
+
+/// Types the name for the leaderboard. Taps, clicks and confirm on a controller save it as it
+/// is, so players without a keyboard can get past the prefilled one, and back on a controller
+/// leaves the run off the leaderboard.
+pub fn type_name(
+    mut commands: Commands,
+    input: ActionInput,
+    mut keyboard_events: EventReader<KeyboardInput>,
+    mut name_entry: ResMut<NameEntry>,
+    (mut leaderboards, mut settings): (ResMut<Leaderboards>, ResMut<Settings>),
+    mut save_requests: EventWriter<SaveRequested>,
+    name_entry_screen: Query<Entity, With<OnNameEntryScreen>>,
+) {
+    // The keys bound to confirm and back also type the name, only controllers use them here
+    let skipped = input.gamepad_just_pressed(Action::Back);
+    let mut submitted =
+        !skipped && (input.pointer_just_pressed() || input.gamepad_just_pressed(Action::Confirm));
+    for event in keyboard_events.read() {
+        if submitted || skipped {
+            break;
+        }
+        if event.state != ButtonState::Pressed {
+            continue;
+        }
+
+        match &event.logical_key {
+            Key::Enter => submitted = true,
+            Key::Backspace => {
+                name_entry.name.pop();
+            }
+            Key::Space => push_name_char(&mut name_entry.name, ' '),
+            Key::Character(characters) => {
+                for character in characters.chars() {
+                    push_name_char(&mut name_entry.name, character);
+                }
+            }
+            _ => {}
+        }
+    }
+    if !submitted && !skipped {
+        return;
+    }
+
+    if submitted {
+        let name = match name_entry.name.trim() {
+            "" => DEFAULT_PLAYER_NAME.to_string(),
+            name => name.to_string(),
+        };
+
+        leaderboards
+            .get_mut(name_entry.preset)
+            .insert(LeaderboardEntry {
+                name: name.clone(),
+                score: name_entry.score,
+                timestamp: unix_timestamp(),
+                duration_secs: name_entry.duration_secs,
+                seed: name_entry.seed,
+            });
+        settings.player_name = name;
+        save_requests.send_default();
+    }
+
+    commands.remove_resource::<NameEntry>();
+    for entity in &name_entry_screen {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn push_name_char(name: &mut String, character: char) {
+    let allowed = character.is_alphanumeric() || matches!(character, ' ' | '-' | '_' | '.');
+    if allowed && name.chars().count() < MAX_NAME_LENGTH {
+        name.push(character);
+    }
+}
+
+fn update_name_entry_text(
+    name_entry: Res<NameEntry>,
+    mut text_q: Query<&mut Text, With<NameEntryText>>,
+) {
+    for mut text in text_q.iter_mut() {
+        text.0 = format!("{}_", name_entry.name);
+    }
+}
+
+fn leaderboard_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    leaderboards: Res<Leaderboards>,
+    preset: Res<Preset>,
+    settings: Res<Settings>,
+) {
+    let leaderboard = leaderboards.get(*preset);
+    let font = asset_server.load("embedded://flappyboi/../assets/fonts/FiraSans-Bold.ttf");
+    let row_font = TextFont {
+        font: font.clone(),
+        font_size: ROW_FONT_SIZE,
+        ..default()
+    };
+
+    commands
+        .spawn((
+            OnLeaderboardScreen,
+            Node {
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                height: Val::Percent(100.),
+                width: Val::Percent(100.),
+                padding: UiRect::all(Val::Px(10.)),
+                row_gap: Val::Px(4.),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new(format!("Leaderboard - {}", preset.name())),
+                TextColor(TEXT_COLOR),
+                TextFont {
+                    font: font.clone(),
+                    font_size: TITLE_FONT_SIZE,
+                    ..default()
+                },
+            ));
+
+            parent
+                .spawn((
+                    Node {
+                        flex_direction: FlexDirection::Column,
+                        padding: UiRect::all(Val::Px(6.)),
+                        row_gap: Val::Px(2.),
+                        ..default()
+                    },
+                    BackgroundColor(PANEL_COLOR),
+                ))
+                .with_children(|table| {
+                    spawn_row(table, &row_font, ["#", "Name", "Score", "Time", "Date"]);
+
+                    if leaderboard.entries.is_empty() {
+                        table.spawn((
+                            Text::new("No runs yet, go play!"),
+                            TextColor(TEXT_COLOR),
+                            row_font.clone(),
+                        ));
+                    }
+
+                    for (rank, entry) in leaderboard.entries.iter().enumerate() {
+                        spawn_row(
+                            table,
+                            &row_font,
+                            [
+                                &(rank + 1).to_string(),
+                                &entry.name,
+                                &entry.score.to_string(),
+                                &format!("{:.1}s", entry.duration_secs),
+                                &format_date(entry.timestamp),
+                            ],
+                        );
+                    }
+                });
+
+            parent.spawn((
+                Text::new(format!(
+                    "Press {} to go back",
+                    settings.bindings.describe(Action::Back)
+                )),
+                TextColor(TEXT_COLOR),
+                TextFont {
+                    font: font.clone(),
+                    font_size: 20.0,
+                    ..default()
+                },
+            ));
+        });
+}
+
+fn spawn_row(parent: &mut ChildBuilder, font: &TextFont, columns: [&str; 5]) {
+    const COLUMN_WIDTHS: [f32; 5] = [28., 120., 50., 60., 90.];
+
+    parent
+        .spawn(Node {
+            flex_direction: FlexDirection::Row,
+            ..default()
+        })
+        .with_children(|row| {
+            for (column, width) in columns.into_iter().zip(COLUMN_WIDTHS) {
+                row.spawn((
+                    Text::new(column),
+                    TextColor(TEXT_COLOR),
+                    font.clone(),
+                    Node {
+                        width: Val::Px(width),
+                        ..default()
+                    },
+                ));
+            }
+        });
+}
+
+fn close_leaderboard(input: ActionInput, mut game_state: ResMut<NextState<GameState>>) {
+    if input.just_pressed(Action::Back)
+        || input.just_pressed(Action::Confirm)
+        || input.pointer_just_pressed()
+    {
+        game_state.set(GameState::Menu);
+    }
+}
+
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Formats a unix timestamp as a `YYYY-MM-DD` UTC date.
+pub fn format_date(timestamp: u64) -> String {
+    // Days to civil date, from http://howardhinnant.github.io/date_algorithms.html
+    let days = (timestamp / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
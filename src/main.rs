@@ -1,20 +1,42 @@
+mod achievements;
+mod autopilot;
+mod cli;
+mod controls;
+mod daily;
 mod debug;
+mod difficulty;
+mod environment;
+mod evolution;
 mod game;
+mod ghost;
+mod headless;
 mod input;
+mod lan;
+mod leaderboard;
+mod medals;
 mod menu;
+mod pause;
+mod persistence;
+mod physics;
 mod pipes;
 mod player;
+mod replay;
+mod scenery;
+mod settings;
+mod sounds;
 mod splash;
+mod stats;
+mod tuning;
+mod versus;
 
-use std::fs::File;
-use std::io::{Read, Write};
-use std::path::PathBuf;
-use std::{env, fs};
+use std::collections::BTreeMap;
 
 use bevy::audio::Volume;
-use bevy::input::common_conditions::input_pressed;
 use bevy::prelude::*;
 use bevy::window::{PresentMode, WindowTheme};
+use difficulty::Preset;
+use input::{Action, ActionInput};
+use settings::Settings;
 
 #[derive(Clone, Copy, Default, Eq, PartialEq, Debug, Hash, States)]
 enum GameState {
@@ -23,59 +45,139 @@ enum GameState {
     Menu,
     DeathScreen,
     Game,
+    Leaderboard,
+    Stats,
+    Controls,
+    Replays,
+    VersusLobby,
+    LanLobby,
 }
 
+/// Whether a run is waiting for the first flap, being played or sits behind the pause
+/// overlay. Only exists while in `GameState::Game`, so every run starts out getting ready.
+#[derive(Clone, Copy, Default, Eq, PartialEq, Debug, Hash, SubStates)]
+#[source(GameState = GameState::Game)]
+enum PlayState {
+    #[default]
+    GetReady,
+    Running,
+    Paused,
+    Settings,
+}
+
+/// Which page of the main menu is showing. Only exists while in `GameState::Menu`, so the
+/// menu always opens on the main page.
+#[derive(Clone, Copy, Default, Eq, PartialEq, Debug, Hash, SubStates)]
+#[source(GameState = GameState::Menu)]
+enum MenuPage {
+    #[default]
+    Main,
+    Modes,
+}
+
+#[derive(Component)]
+struct ThemeSong;
+
 #[derive(Resource, Deref, DerefMut)]
 pub struct Score(pub usize);
 
-#[derive(Resource, Deref, DerefMut)]
-pub struct Highscore(pub usize);
-
-fn main() {
-    let mut app = App::new();
-
-    app.add_plugins(
-        DefaultPlugins
-            .set(ImagePlugin::default_nearest())
-            .set(WindowPlugin {
-                primary_window: Some(Window {
-                    title: "Flappy Birb".into(),
-                    name: Some("bevy.app".into()),
-                    resolution: (400., 400.).into(),
-                    window_theme: Some(WindowTheme::Dark),
-                    present_mode: PresentMode::AutoNoVsync,
-                    enabled_buttons: bevy::window::EnabledButtons {
-                        maximize: false,
+/// Best score for every difficulty preset, they are never compared with each other.
+#[derive(Resource, Default, Clone, Debug)]
+pub struct Highscores(pub BTreeMap<Preset, usize>);
+
+impl Highscores {
+    pub fn get(&self, preset: Preset) -> usize {
+        self.0.get(&preset).copied().unwrap_or(0)
+    }
+}
+
+fn main() -> AppExit {
+    if std::env::args().any(|arg| arg == "--headless") {
+        return headless::run();
+    }
+    if std::env::args().any(|arg| arg == "--env") {
+        return environment::run();
+    }
+
+    App::new()
+        .add_plugins(
+            DefaultPlugins
+                .set(ImagePlugin::default_nearest())
+                .set(WindowPlugin {
+                    primary_window: Some(Window {
+                        title: "Flappy Birb".into(),
+                        name: Some("bevy.app".into()),
+                        resolution: (400., 400.).into(),
+                        window_theme: Some(WindowTheme::Dark),
+                        present_mode: PresentMode::AutoNoVsync,
+                        enabled_buttons: bevy::window::EnabledButtons {
+                            maximize: false,
+                            ..default()
+                        },
+                        visible: true,
                         ..default()
-                    },
-                    visible: true,
+                    }),
                     ..default()
                 }),
-                ..default()
-            }),
-    )
-    .add_systems(Update, exit_game.run_if(input_pressed(KeyCode::Escape)))
-    .add_systems(Startup, setup)
-    //.add_plugins(debug::DebugPlugin)
-    .init_state::<GameState>()
-    .add_plugins((game::GamePlugin, splash::SplashPlugin, menu::MenuPlugin))
-    .run();
+        )
+        // Parsed after the log plugin is in, so mistakes in the arguments get reported
+        .insert_resource(cli::CliArgs::parse())
+        .add_systems(
+            Update,
+            // Only from the main menu, where there is nothing to go back to
+            exit_game.run_if(in_state(MenuPage::Main)),
+        )
+        .add_systems(Startup, setup)
+        .add_systems(
+            Update,
+            apply_music_volume.run_if(resource_changed::<Settings>),
+        )
+        //.add_plugins(debug::DebugPlugin)
+        .init_state::<GameState>()
+        .add_sub_state::<PlayState>()
+        .add_sub_state::<MenuPage>()
+        .add_plugins((
+            persistence::PersistencePlugin,
+            tuning::TuningPlugin,
+            physics::PhysicsPlugin,
+            difficulty::DifficultyPlugin,
+            stats::StatsPlugin,
+            game::GamePlugin,
+            input::InputPlugin,
+            sounds::SoundsPlugin,
+            scenery::SceneryPlugin,
+            replay::ReplayPlugin,
+            ghost::GhostPlugin,
+        ))
+        .add_plugins((
+            splash::SplashPlugin,
+            menu::MenuPlugin,
+            leaderboard::LeaderboardPlugin,
+            achievements::AchievementsPlugin,
+            medals::MedalsPlugin,
+            controls::ControlsPlugin,
+            daily::DailyPlugin,
+            pause::PausePlugin,
+            autopilot::AttractModePlugin,
+            evolution::EvolutionPlugin,
+            versus::VersusPlugin,
+            lan::LanPlugin,
+        ))
+        .run()
 }
 
-fn exit_game(mut exit: EventWriter<AppExit>) {
-    exit.send(AppExit::Success);
+fn exit_game(input: ActionInput, mut exit: EventWriter<AppExit>) {
+    if input.just_pressed(Action::Quit) {
+        exit.send(AppExit::Success);
+    }
 }
 
-fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
-    let file_score = read_highscore_from_file();
-
+fn setup(mut commands: Commands, asset_server: Res<AssetServer>, settings: Res<Settings>) {
     let theme_song =
         asset_server.load::<AudioSource>("embedded://flappyboi/../assets/audio/themesong.ogg");
-    commands.insert_resource(Score(0));
-    commands.insert_resource(Highscore(file_score));
 
     commands.spawn((
-        Camera2d::default(),
+        Camera2d,
         OrthographicProjection {
             viewport_origin: Vec2::new(0., 0.),
             ..OrthographicProjection::default_2d()
@@ -83,68 +185,25 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
     ));
 
     commands.spawn((
+        ThemeSong,
         AudioPlayer(theme_song.clone()),
         PlaybackSettings {
             mode: bevy::audio::PlaybackMode::Loop,
-            volume: Volume::new(0.5),
+            volume: Volume::new(settings.music_volume),
             ..default()
         },
     ));
 }
 
+fn apply_music_volume(settings: Res<Settings>, sink_q: Query<&AudioSink, With<ThemeSong>>) {
+    for sink in sink_q.iter() {
+        sink.set_volume(settings.music_volume);
+    }
+}
+
 // Generic system that takes a component as a parameter, and will despawn all entities with that component
 fn despawn_screen<T: Component>(to_despawn: Query<Entity, With<T>>, mut commands: Commands) {
     for entity in &to_despawn {
         commands.entity(entity).despawn_recursive();
     }
 }
-
-fn read_highscore_from_file() -> usize {
-    // Get the LOCALAPPDATA path
-    let local_app_data = match env::var("LOCALAPPDATA") {
-        Ok(path) => path,
-        Err(_) => return 0,
-    };
-
-    // Construct the path to the highscore file
-    let mut highscore_path = PathBuf::from(local_app_data);
-    highscore_path.push("flappyboi");
-    highscore_path.push("highscore.txt");
-
-    // Ensure the directory exists
-    if let Some(parent) = highscore_path.parent() {
-        if fs::create_dir_all(parent).is_err() {
-            error!("Failed to create directories in localappdata");
-            return 0;
-        }
-    }
-
-    // Create the file if it doesn't exist and set default highscore to 0
-    if !highscore_path.exists() {
-        if let Ok(mut file) = File::create(&highscore_path) {
-            if file.write_all(b"0").is_err() {
-                return 0;
-            } else {
-                error!("Failed to write default 0 to file")
-            }
-        } else {
-            error!("Failed to create highscore file");
-            return 0;
-        }
-    }
-
-    // Read the highscore into an i32
-    let mut file = match File::open(&highscore_path) {
-        Ok(file) => file,
-        Err(_) => return 0,
-    };
-
-    let mut contents = String::new();
-    if file.read_to_string(&mut contents).is_err() {
-        error!("Failed to read files content");
-        return 0;
-    }
-
-    // Parse the highscore
-    contents.trim().parse().unwrap_or(0)
-}
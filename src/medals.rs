@@ -0,0 +1,291 @@
+use super::{despawn_screen, GameState, Score};
+use crate::daily::DailyChallenge;
+use crate::game::{racing, BestScore, CurrentRun, GameMode};
+use bevy::prelude::*;
+use serde::Deserialize;
+
+const MEDAL_SIZE: f32 = 64.0;
+// How long to wait before the medal pops in, and how long the pop takes
+const REVEAL_DELAY_SECS: f32 = 0.3;
+const REVEAL_SECS: f32 = 0.5;
+
+const TEXT_COLOR: Color = Color::srgb(0.1, 0., 0.);
+const NEW_BEST_COLOR: Color = Color::srgb(0.85, 0.1, 0.1);
+const PANEL_COLOR: Color = Color::srgba(1., 1., 1., 0.8);
+const NO_MEDAL_COLOR: Color = Color::srgba(0., 0., 0., 0.15);
+
+pub struct MedalsPlugin;
+
+impl Plugin for MedalsPlugin {
+    fn build(&self, app: &mut App) {
+        let thresholds: MedalThresholds = ron::from_str(include_str!("../assets/medals.ron"))
+            .expect("assets/medals.ron should be valid");
+
+        app.insert_resource(thresholds)
+            // Versus and races show their results where the medal would be
+            .add_systems(
+                OnEnter(GameState::DeathScreen),
+                medal_panel_setup.run_if(not(resource_equals(GameMode::Versus).or(racing))),
+            )
+            .add_systems(
+                OnExit(GameState::DeathScreen),
+                despawn_screen::<OnMedalPanel>,
+            )
+            .add_systems(
+                Update,
+                reveal_medal.run_if(in_state(GameState::DeathScreen)),
+            );
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Medal {
+    Bronze,
+    Silver,
+    Gold,
+    Platinum,
+}
+
+impl Medal {
+    fn name(self) -> &'static str {
+        match self {
+            Medal::Bronze => "Bronze",
+            Medal::Silver => "Silver",
+            Medal::Gold => "Gold",
+            Medal::Platinum => "Platinum",
+        }
+    }
+
+    fn color(self) -> Color {
+        match self {
+            Medal::Bronze => Color::srgb(0.8, 0.5, 0.2),
+            Medal::Silver => Color::srgb(0.75, 0.75, 0.8),
+            Medal::Gold => Color::srgb(1.0, 0.8, 0.1),
+            Medal::Platinum => Color::srgb(0.85, 0.95, 1.0),
+        }
+    }
+}
+
+/// Lowest score for each medal, loaded from `assets/medals.ron`.
+#[derive(Resource, Deserialize, Clone, Debug)]
+pub struct MedalThresholds {
+    pub bronze: usize,
+    pub silver: usize,
+    pub gold: usize,
+    pub platinum: usize,
+}
+
+impl MedalThresholds {
+    pub fn medal_for(&self, score: usize) -> Option<Medal> {
+        [
+            (Medal::Platinum, self.platinum),
+            (Medal::Gold, self.gold),
+            (Medal::Silver, self.silver),
+            (Medal::Bronze, self.bronze),
+        ]
+        .into_iter()
+        .find(|&(_, threshold)| score >= threshold)
+        .map(|(medal, _)| medal)
+    }
+}
+
+#[derive(Component)]
+struct OnMedalPanel;
+
+/// The medal itself, grows in once the timer has run out.
+#[derive(Component)]
+struct MedalReveal(Timer);
+
+/// Shown once the medal has finished popping in.
+#[derive(Component)]
+struct NewBestBadge;
+
+fn medal_panel_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    thresholds: Res<MedalThresholds>,
+    score: Res<Score>,
+    best: BestScore,
+    daily: Res<DailyChallenge>,
+    run: Res<CurrentRun>,
+) {
+    let font = asset_server.load("embedded://flappyboi/../assets/fonts/FiraSans-Bold.ttf");
+    let medal = thresholds.medal_for(**score);
+    let new_best = **score > run.starting_highscore && best.mode().is_played();
+
+    commands
+        .spawn((
+            OnMedalPanel,
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.),
+                top: Val::Px(110.),
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn((
+                    Node {
+                        flex_direction: FlexDirection::Row,
+                        align_items: AlignItems::Center,
+                        padding: UiRect::all(Val::Px(10.)),
+                        column_gap: Val::Px(14.),
+                        ..default()
+                    },
+                    BackgroundColor(PANEL_COLOR),
+                    BorderRadius::all(Val::Px(8.)),
+                ))
+                .with_children(|panel| {
+                    // Fixed size slot so the panel doesn't jump around while the medal grows
+                    panel
+                        .spawn(Node {
+                            width: Val::Px(MEDAL_SIZE),
+                            height: Val::Px(MEDAL_SIZE),
+                            align_items: AlignItems::Center,
+                            justify_content: JustifyContent::Center,
+                            ..default()
+                        })
+                        .with_child((
+                            MedalReveal(Timer::from_seconds(
+                                REVEAL_DELAY_SECS + REVEAL_SECS,
+                                TimerMode::Once,
+                            )),
+                            Node {
+                                width: Val::Px(0.),
+                                height: Val::Px(0.),
+                                ..default()
+                            },
+                            BackgroundColor(medal.map_or(NO_MEDAL_COLOR, Medal::color)),
+                            BorderRadius::MAX,
+                        ));
+
+                    panel
+                        .spawn(Node {
+                            flex_direction: FlexDirection::Column,
+                            ..default()
+                        })
+                        .with_children(|column| {
+                            let text_font = TextFont {
+                                font: font.clone(),
+                                font_size: 20.0,
+                                ..default()
+                            };
+
+                            column.spawn((
+                                Text::new(medal.map_or("No medal", Medal::name)),
+                                TextColor(TEXT_COLOR),
+                                text_font.clone(),
+                            ));
+                            column.spawn((
+                                Text::new(format!("Score {}", **score)),
+                                TextColor(TEXT_COLOR),
+                                text_font.clone(),
+                            ));
+                            match best.mode() {
+                                GameMode::Classic => {
+                                    column.spawn((
+                                        Text::new(format!("Best {}", best.get())),
+                                        TextColor(TEXT_COLOR),
+                                        text_font.clone(),
+                                    ));
+                                }
+                                GameMode::Daily => {
+                                    let today =
+                                        run.day.map(|day| daily.on(day)).unwrap_or_default();
+                                    column.spawn((
+                                        Text::new(format!("Today's best {}", today.best)),
+                                        TextColor(TEXT_COLOR),
+                                        text_font.clone(),
+                                    ));
+                                    column.spawn((
+                                        Text::new(format!("Attempt {} today", today.attempts)),
+                                        TextColor(TEXT_COLOR),
+                                        text_font.clone(),
+                                    ));
+                                }
+                                GameMode::Replay(preset) => {
+                                    column.spawn((
+                                        Text::new(format!("Replay on {}", preset.name())),
+                                        TextColor(TEXT_COLOR),
+                                        text_font.clone(),
+                                    ));
+                                }
+                                GameMode::Demo => {
+                                    column.spawn((
+                                        Text::new("Autopilot demo"),
+                                        TextColor(TEXT_COLOR),
+                                        text_font.clone(),
+                                    ));
+                                }
+                                GameMode::Evolution => {
+                                    column.spawn((
+                                        Text::new("Evolution"),
+                                        TextColor(TEXT_COLOR),
+                                        text_font.clone(),
+                                    ));
+                                }
+                                GameMode::Versus => {
+                                    column.spawn((
+                                        Text::new("Versus"),
+                                        TextColor(TEXT_COLOR),
+                                        text_font.clone(),
+                                    ));
+                                }
+                                GameMode::Race { preset, .. } => {
+                                    column.spawn((
+                                        Text::new(format!("LAN race on {}", preset.name())),
+                                        TextColor(TEXT_COLOR),
+                                        text_font.clone(),
+                                    ));
+                                }
+                            }
+                            if let Some(seed) = run.seed {
+                                column.spawn((
+                                    Text::new(format!("Seed {}", seed)),
+                                    TextColor(TEXT_COLOR),
+                                    TextFont {
+                                        font: font.clone(),
+                                        font_size: 14.0,
+                                        ..default()
+                                    },
+                                ));
+                            }
+                            if new_best {
+                                column.spawn((
+                                    NewBestBadge,
+                                    Text::new("NEW BEST!"),
+                                    TextColor(NEW_BEST_COLOR),
+                                    text_font.clone(),
+                                    Visibility::Hidden,
+                                ));
+                            }
+                        });
+                });
+        });
+}
+
+fn reveal_medal(
+    time: Res<Time>,
+    mut medal_q: Query<(&mut MedalReveal, &mut Node)>,
+    mut badge_q: Query<&mut Visibility, With<NewBestBadge>>,
+) {
+    for (mut reveal, mut node) in medal_q.iter_mut() {
+        if reveal.0.finished() {
+            continue;
+        }
+        reveal.0.tick(time.delta());
+
+        let t = ((reveal.0.elapsed_secs() - REVEAL_DELAY_SECS) / REVEAL_SECS).clamp(0., 1.);
+        let size = EasingCurve::new(0., MEDAL_SIZE, EaseFunction::BackOut).sample_clamped(t);
+        node.width = Val::Px(size);
+        node.height = Val::Px(size);
+
+        if reveal.0.finished() {
+            for mut visibility in badge_q.iter_mut() {
+                *visibility = Visibility::Inherited;
+            }
+        }
+    }
+}
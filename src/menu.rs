@@ -1,26 +1,41 @@
-use crate::Highscore;
+use crate::daily::DailyChallenge;
+use crate::difficulty::Preset;
+use crate::game::{racing, BestScore, GameMode};
+use crate::input::{Action, ActionInput};
+use crate::leaderboard::{type_name, NameEntry};
+use crate::persistence::SaveRequested;
+use crate::settings::Settings;
 
-use super::{despawn_screen, GameState, Score};
+use super::{despawn_screen, GameState, MenuPage, PlayState, Score};
 use bevy::asset::embedded_asset;
 use bevy::prelude::*;
-use std::env;
-use std::fs::{self, File};
-use std::io::Write;
-use std::path::PathBuf;
 
 pub struct MenuPlugin;
 
 impl Plugin for MenuPlugin {
     fn build(&self, app: &mut App) {
         embedded_asset!(app, "../assets/fonts/FiraSans-Bold.ttf");
-        app.add_systems(OnEnter(GameState::Menu), (menu_setup, hide_score))
+        app.init_resource::<MenuSelection>()
+            .add_systems(OnEnter(GameState::Menu), hide_score)
+            .add_systems(OnEnter(MenuPage::Main), main_menu_setup)
+            .add_systems(OnEnter(MenuPage::Modes), modes_menu_setup)
+            .add_systems(OnExit(MenuPage::Main), despawn_screen::<OnMenuScreen>)
+            .add_systems(OnExit(MenuPage::Modes), despawn_screen::<OnMenuScreen>)
+            .add_systems(OnEnter(GameState::Game), show_score)
+            .add_systems(OnEnter(PlayState::GetReady), get_ready_setup)
             .add_systems(
-                OnExit(GameState::Menu),
-                (despawn_screen::<OnMenuScreen>, show_score),
+                OnExit(PlayState::GetReady),
+                despawn_screen::<OnGetReadyScreen>,
             )
             .add_systems(
                 OnEnter(GameState::DeathScreen),
-                (show_score, show_highscore, death_menu_setup),
+                (
+                    show_score,
+                    // Versus has its own results, the solo best would only confuse them
+                    show_highscore.run_if(not(resource_equals(GameMode::Versus))),
+                    // A race is started again by its host
+                    death_menu_setup.run_if(not(resource_equals(GameMode::Demo)).and(not(racing))),
+                ),
             )
             .add_systems(
                 OnExit(GameState::DeathScreen),
@@ -30,8 +45,23 @@ impl Plugin for MenuPlugin {
             .add_systems(Update, update_scoreboard.run_if(in_state(GameState::Game)))
             .add_systems(
                 Update,
-                (close_menu_action)
-                    .run_if(in_state(GameState::Menu).or(in_state(GameState::DeathScreen))),
+                (menu_selection, menu_action, update_menu_buttons)
+                    .chain()
+                    .run_if(in_state(GameState::Menu)),
+            )
+            .add_systems(Update, leave_modes_page.run_if(in_state(MenuPage::Modes)))
+            .add_systems(
+                Update,
+                (
+                    // Before the name is saved, so the same tap or key doesn't retry as well
+                    close_menu_action.before(type_name).run_if(
+                        not(resource_exists::<NameEntry>)
+                            .and(not(resource_equals(GameMode::Demo)))
+                            .and(not(racing)),
+                    ),
+                    toggle_retry_prompt,
+                )
+                    .run_if(in_state(GameState::DeathScreen)),
             );
     }
 }
@@ -41,6 +71,28 @@ const SCOREBOARD_TEXT_PADDING: Val = Val::Px(5.0);
 const TEXT_COLOR: Color = Color::BLACK;
 const SCORE_COLOR: Color = Color::srgb(1.0, 0.7, 0.1);
 const RETRY_TEXT_COLOR: Color = Color::srgb(0.1, 0., 0.);
+const BUTTON_COLOR: Color = Color::srgba(1., 1., 1., 0.6);
+const SELECTED_BUTTON_COLOR: Color = Color::srgb(1.0, 0.7, 0.1);
+
+// The difficulty and daily labels are filled in by `update_menu_buttons`
+const MAIN_BUTTONS: [(MenuButtonAction, &str); 9] = [
+    (MenuButtonAction::Play, "Play"),
+    (MenuButtonAction::Difficulty, ""),
+    (MenuButtonAction::Daily, ""),
+    (MenuButtonAction::Modes, "Modes"),
+    (MenuButtonAction::Leaderboard, "Leaderboard"),
+    (MenuButtonAction::Replays, "Replays"),
+    (MenuButtonAction::Stats, "Stats"),
+    (MenuButtonAction::Controls, "Controls"),
+    (MenuButtonAction::Quit, "Quit"),
+];
+
+const MODE_BUTTONS: [(MenuButtonAction, &str); 4] = [
+    (MenuButtonAction::Evolution, "Evolution"),
+    (MenuButtonAction::Versus, "Versus"),
+    (MenuButtonAction::LanRace, "LAN Race"),
+    (MenuButtonAction::Back, "Back"),
+];
 
 #[derive(Component)]
 struct OnMenuScreen;
@@ -48,6 +100,39 @@ struct OnMenuScreen;
 #[derive(Component)]
 struct OnDeathScreen;
 
+#[derive(Component)]
+struct OnGetReadyScreen;
+
+#[derive(Component)]
+struct RetryPrompt;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MenuButtonAction {
+    Play,
+    Difficulty,
+    Daily,
+    Modes,
+    Evolution,
+    Versus,
+    LanRace,
+    Back,
+    Leaderboard,
+    Replays,
+    Stats,
+    Controls,
+    Quit,
+}
+
+#[derive(Component)]
+struct MenuButton {
+    index: usize,
+    action: MenuButtonAction,
+}
+
+/// Index of the menu button that space/enter activates.
+#[derive(Resource, Default)]
+struct MenuSelection(usize);
+
 #[derive(Component)]
 struct ScoreboardUi;
 
@@ -109,10 +194,190 @@ fn setup_score_ui(mut commands: Commands) {
         ));
 }
 
-fn menu_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+fn main_menu_setup(
+    commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut selection: ResMut<MenuSelection>,
+) {
+    selection.0 = 0;
+    spawn_menu(commands, &asset_server, &MAIN_BUTTONS);
+}
+
+fn modes_menu_setup(
+    commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut selection: ResMut<MenuSelection>,
+) {
+    selection.0 = 0;
+    spawn_menu(commands, &asset_server, &MODE_BUTTONS);
+}
+
+fn spawn_menu(
+    mut commands: Commands,
+    asset_server: &AssetServer,
+    buttons: &[(MenuButtonAction, &str)],
+) {
+    let font = asset_server.load("embedded://flappyboi/../assets/fonts/FiraSans-Bold.ttf");
+
     commands
         .spawn((
             OnMenuScreen,
+            Node {
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                height: Val::Percent(100.),
+                width: Val::Percent(100.),
+                row_gap: Val::Px(6.),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            for (index, &(action, label)) in buttons.iter().enumerate() {
+                parent
+                    .spawn((
+                        MenuButton { index, action },
+                        Button,
+                        Node {
+                            width: Val::Px(200.),
+                            padding: UiRect::all(Val::Px(5.)),
+                            justify_content: JustifyContent::Center,
+                            ..default()
+                        },
+                        BackgroundColor(BUTTON_COLOR),
+                    ))
+                    .with_child((
+                        Text::new(label),
+                        TextColor(RETRY_TEXT_COLOR),
+                        TextFont {
+                            font: font.clone(),
+                            font_size: 22.0,
+                            ..default()
+                        },
+                    ));
+            }
+        });
+}
+
+fn menu_selection(
+    input: ActionInput,
+    interaction_q: Query<(&Interaction, &MenuButton), Changed<Interaction>>,
+    button_q: Query<&MenuButton>,
+    mut selection: ResMut<MenuSelection>,
+) {
+    let count = button_q.iter().count().max(1);
+    if input.just_pressed(Action::Down) {
+        selection.0 = (selection.0 + 1) % count;
+    }
+    if input.just_pressed(Action::Up) {
+        selection.0 = (selection.0 + count - 1) % count;
+    }
+
+    for (interaction, button) in &interaction_q {
+        if *interaction != Interaction::None {
+            selection.0 = button.index;
+        }
+    }
+}
+
+fn menu_action(
+    input: ActionInput,
+    button_q: Query<(Ref<Interaction>, &MenuButton)>,
+    selection: Res<MenuSelection>,
+    (mut preset, mut mode): (ResMut<Preset>, ResMut<GameMode>),
+    (mut game_state, mut menu_page): (ResMut<NextState<GameState>>, ResMut<NextState<MenuPage>>),
+    mut exit: EventWriter<AppExit>,
+    mut save_requests: EventWriter<SaveRequested>,
+) {
+    let clicked = button_q
+        .iter()
+        .find(|(interaction, _)| interaction.is_changed() && **interaction == Interaction::Pressed)
+        .map(|(_, button)| button.action);
+    let confirmed = input
+        .just_pressed(Action::Confirm)
+        .then(|| {
+            button_q
+                .iter()
+                .find(|(_, button)| button.index == selection.0)
+                .map(|(_, button)| button.action)
+        })
+        .flatten();
+
+    match clicked.or(confirmed) {
+        Some(MenuButtonAction::Play) => {
+            *mode = GameMode::Classic;
+            game_state.set(GameState::Game);
+        }
+        Some(MenuButtonAction::Daily) => {
+            *mode = GameMode::Daily;
+            game_state.set(GameState::Game);
+        }
+        Some(MenuButtonAction::Modes) => menu_page.set(MenuPage::Modes),
+        Some(MenuButtonAction::Back) => menu_page.set(MenuPage::Main),
+        Some(MenuButtonAction::Evolution) => {
+            *mode = GameMode::Evolution;
+            game_state.set(GameState::Game);
+        }
+        Some(MenuButtonAction::Difficulty) => {
+            *preset = preset.next();
+            save_requests.send_default();
+        }
+        Some(MenuButtonAction::Versus) => game_state.set(GameState::VersusLobby),
+        Some(MenuButtonAction::LanRace) => game_state.set(GameState::LanLobby),
+        Some(MenuButtonAction::Leaderboard) => game_state.set(GameState::Leaderboard),
+        Some(MenuButtonAction::Replays) => game_state.set(GameState::Replays),
+        Some(MenuButtonAction::Stats) => game_state.set(GameState::Stats),
+        Some(MenuButtonAction::Controls) => game_state.set(GameState::Controls),
+        Some(MenuButtonAction::Quit) => {
+            exit.send(AppExit::Success);
+        }
+        None => {}
+    }
+}
+
+fn leave_modes_page(input: ActionInput, mut menu_page: ResMut<NextState<MenuPage>>) {
+    if input.just_pressed(Action::Back) {
+        menu_page.set(MenuPage::Main);
+    }
+}
+
+fn update_menu_buttons(
+    selection: Res<MenuSelection>,
+    preset: Res<Preset>,
+    daily: Res<DailyChallenge>,
+    mut button_q: Query<(&MenuButton, &Children, &mut BackgroundColor)>,
+    mut text_q: Query<&mut Text>,
+) {
+    for (button, children, mut background) in button_q.iter_mut() {
+        background.0 = if button.index == selection.0 {
+            SELECTED_BUTTON_COLOR
+        } else {
+            BUTTON_COLOR
+        };
+
+        let label = match button.action {
+            MenuButtonAction::Difficulty => format!("Difficulty: {}", preset.name()),
+            MenuButtonAction::Daily => match daily.today().attempts {
+                0 => "Daily Challenge".to_string(),
+                attempts => format!("Daily ({} tries)", attempts),
+            },
+            _ => continue,
+        };
+        let mut texts = text_q.iter_many_mut(children);
+        while let Some(mut text) = texts.fetch_next() {
+            text.0.clone_from(&label);
+        }
+    }
+}
+
+fn death_menu_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    settings: Res<Settings>,
+) {
+    commands
+        .spawn((
+            OnDeathScreen,
             Node {
                 align_items: AlignItems::End,
                 justify_content: JustifyContent::Center,
@@ -124,7 +389,12 @@ fn menu_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
         ))
         .with_children(|parent| {
             parent.spawn((
-                Text("Press space to play".to_string()),
+                RetryPrompt,
+                Text(format!(
+                    "Press {} or tap to retry\n{} for the menu",
+                    settings.bindings.describe(Action::Confirm),
+                    settings.bindings.describe(Action::Back)
+                )),
                 TextColor(RETRY_TEXT_COLOR),
                 TextFont {
                     font: asset_server
@@ -136,27 +406,50 @@ fn menu_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
         });
 }
 
-fn death_menu_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+fn get_ready_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    settings: Res<Settings>,
+    mode: Res<GameMode>,
+) {
+    let hint = match *mode {
+        GameMode::Versus => "Any player flaps to start".to_string(),
+        _ => format!(
+            "Press {} or tap to start",
+            settings.bindings.describe(Action::Flap)
+        ),
+    };
+    let font = asset_server.load("embedded://flappyboi/../assets/fonts/FiraSans-Bold.ttf");
+
     commands
         .spawn((
-            OnDeathScreen,
+            OnGetReadyScreen,
             Node {
-                align_items: AlignItems::End,
-                justify_content: JustifyContent::Center,
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::SpaceBetween,
                 height: Val::Percent(100.),
                 width: Val::Percent(100.),
-                bottom: Val::Percent(10.),
+                padding: UiRect::axes(Val::Px(0.), Val::Percent(15.)),
                 ..default()
             },
         ))
         .with_children(|parent| {
             parent.spawn((
-                Text("Press space to retry".to_string()),
+                Text::new("Get Ready!"),
+                TextColor(SCORE_COLOR),
+                TextFont {
+                    font: font.clone(),
+                    font_size: 40.0,
+                    ..default()
+                },
+            ));
+            parent.spawn((
+                Text(hint),
                 TextColor(RETRY_TEXT_COLOR),
                 TextFont {
-                    font: asset_server
-                        .load("embedded://flappyboi/../assets/fonts/FiraSans-Bold.ttf"),
-                    font_size: 30.0,
+                    font: font.clone(),
+                    font_size: 26.0,
                     ..default()
                 },
             ));
@@ -172,10 +465,8 @@ fn hide_score(mut score_ui: Single<(&mut ScoreboardUi, &mut Node), Without<Highs
 }
 
 fn show_highscore(
-    highscore: Res<Highscore>,
     mut highscore_ui: Single<(&mut HighscoreboardUi, &mut Node), Without<ScoreboardUi>>,
 ) {
-    write_highscore(**highscore);
     highscore_ui.1.as_mut().display = Display::Block;
 }
 
@@ -185,56 +476,37 @@ fn hide_highscore(
     highscore_ui.1.as_mut().display = Display::None;
 }
 
-fn close_menu_action(
-    keys: Res<ButtonInput<KeyCode>>,
-    mut score: ResMut<Score>,
-    mut game_state: ResMut<NextState<GameState>>,
-) {
-    if keys.just_pressed(KeyCode::Space) {
-        **score = 0;
+fn close_menu_action(input: ActionInput, mut game_state: ResMut<NextState<GameState>>) {
+    if input.just_pressed(Action::Confirm) || input.pointer_just_pressed() {
         game_state.set(GameState::Game);
+    } else if input.just_pressed(Action::Back) {
+        game_state.set(GameState::Menu);
+    }
+}
+
+// The retry prompt stays hidden while a name is being typed for the leaderboard
+fn toggle_retry_prompt(
+    name_entry: Option<Res<NameEntry>>,
+    mut prompt_q: Query<&mut Visibility, With<RetryPrompt>>,
+) {
+    for mut visibility in prompt_q.iter_mut() {
+        *visibility = if name_entry.is_some() {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        };
     }
 }
 
 fn update_scoreboard(
     score: Res<Score>,
-    highscore: Res<Highscore>,
+    best: BestScore,
     score_root: Single<Entity, (With<ScoreboardUi>, With<Text>)>,
     highscore_root: Single<Entity, (With<HighscoreboardUi>, With<Text>)>,
     mut writer: TextUiWriter,
 ) {
     *writer.text(*score_root, 1) = score.to_string();
-    *writer.text(*highscore_root, 1) = highscore.to_string();
-}
-
-fn write_highscore(highscore: usize) {
-    // Get the LOCALAPPDATA path
-    let local_app_data = match env::var("LOCALAPPDATA") {
-        Ok(path) => path,
-        Err(_) => {
-            error!("Could not find LOCALAPPDATA environment variable");
-            return;
-        }
-    };
-
-    // Construct the path to the highscore file
-    let mut highscore_path = PathBuf::from(local_app_data);
-    highscore_path.push("flappyboi");
-    highscore_path.push("highscore.txt");
-
-    // Ensure the directory exists
-    if let Some(parent) = highscore_path.parent() {
-        if let Err(e) = fs::create_dir_all(parent) {
-            error!("Failed to create directory: {}", e);
-            return;
-        }
-    }
-
-    // Write the highscore to the file
-    if let Err(e) = File::create(&highscore_path).and_then(|mut file| write!(file, "{}", highscore))
-    {
-        error!("Failed to write highscore: {}", e);
-    }
+    *writer.text(*highscore_root, 1) = best.get().to_string();
 }
 
 fn add_to_px(val: Val, amount: f32) -> Val {
@@ -0,0 +1,328 @@
+use super::{GameState, PlayState};
+use crate::game::{racing, restart_run, GameMode};
+use crate::input::{Action, ActionInput};
+use crate::persistence::SaveRequested;
+use crate::settings::Settings;
+use bevy::prelude::*;
+use bevy::window::WindowFocused;
+
+const TITLE_FONT_SIZE: f32 = 40.0;
+const BUTTON_FONT_SIZE: f32 = 26.0;
+const TITLE_COLOR: Color = Color::WHITE;
+const TEXT_COLOR: Color = Color::srgb(0.1, 0., 0.);
+const OVERLAY_COLOR: Color = Color::srgba(0., 0., 0., 0.45);
+const BUTTON_COLOR: Color = Color::srgba(1., 1., 1., 0.6);
+const SELECTED_BUTTON_COLOR: Color = Color::srgb(1.0, 0.7, 0.1);
+
+const MUSIC_VOLUME_STEP: f32 = 0.1;
+
+const PAUSE_BUTTONS: [(OverlayAction, &str); 4] = [
+    (OverlayAction::Resume, "Resume"),
+    (OverlayAction::Restart, "Restart"),
+    (OverlayAction::Settings, "Settings"),
+    (OverlayAction::QuitToMenu, "Quit to Menu"),
+];
+
+// The music, ghost and demo labels are filled in by `update_overlay_buttons`
+const SETTINGS_BUTTONS: [(OverlayAction, &str); 4] = [
+    (OverlayAction::MusicVolume, ""),
+    (OverlayAction::Ghost, ""),
+    (OverlayAction::AttractMode, ""),
+    (OverlayAction::Back, "Back"),
+];
+
+pub struct PausePlugin;
+
+impl Plugin for PausePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<OverlaySelection>()
+            .add_event::<OverlayActivated>()
+            .add_systems(OnEnter(PlayState::Paused), pause_overlay_setup)
+            .add_systems(OnEnter(PlayState::Settings), settings_overlay_setup)
+            .add_systems(OnExit(PlayState::Paused), despawn_overlay)
+            .add_systems(
+                OnExit(PlayState::Settings),
+                (despawn_overlay, save_settings),
+            )
+            .add_systems(
+                Update,
+                // Any key ends the demo instead, and a race doesn't wait for anyone
+                (toggle_pause, pause_on_focus_lost).run_if(
+                    in_state(GameState::Game)
+                        .and(not(resource_equals(GameMode::Demo)))
+                        .and(not(racing)),
+                ),
+            )
+            .add_systems(
+                Update,
+                (
+                    overlay_selection,
+                    overlay_action,
+                    apply_overlay_action,
+                    update_overlay_buttons,
+                )
+                    .chain()
+                    .run_if(in_state(PlayState::Paused).or(in_state(PlayState::Settings))),
+            );
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OverlayAction {
+    Resume,
+    Restart,
+    Settings,
+    QuitToMenu,
+    MusicVolume,
+    Ghost,
+    AttractMode,
+    Back,
+}
+
+#[derive(Component)]
+struct OnPauseOverlay;
+
+#[derive(Component)]
+struct OverlayButton {
+    index: usize,
+    action: OverlayAction,
+}
+
+#[derive(Event)]
+struct OverlayActivated(OverlayAction);
+
+/// Index of the overlay button that confirm activates.
+#[derive(Resource, Default)]
+struct OverlaySelection(usize);
+
+fn pause_overlay_setup(
+    commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut selection: ResMut<OverlaySelection>,
+) {
+    selection.0 = 0;
+    spawn_overlay(commands, &asset_server, "Paused", &PAUSE_BUTTONS);
+}
+
+fn settings_overlay_setup(
+    commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut selection: ResMut<OverlaySelection>,
+) {
+    selection.0 = 0;
+    spawn_overlay(commands, &asset_server, "Settings", &SETTINGS_BUTTONS);
+}
+
+fn spawn_overlay(
+    mut commands: Commands,
+    asset_server: &AssetServer,
+    title: &str,
+    buttons: &[(OverlayAction, &str)],
+) {
+    let font = asset_server.load("embedded://flappyboi/../assets/fonts/FiraSans-Bold.ttf");
+
+    commands
+        .spawn((
+            OnPauseOverlay,
+            Node {
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                height: Val::Percent(100.),
+                width: Val::Percent(100.),
+                row_gap: Val::Px(10.),
+                ..default()
+            },
+            BackgroundColor(OVERLAY_COLOR),
+            // Above the scoreboard, below achievement toasts
+            GlobalZIndex(5),
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new(title),
+                TextColor(TITLE_COLOR),
+                TextFont {
+                    font: font.clone(),
+                    font_size: TITLE_FONT_SIZE,
+                    ..default()
+                },
+            ));
+
+            for (index, &(action, label)) in buttons.iter().enumerate() {
+                parent
+                    .spawn((
+                        OverlayButton { index, action },
+                        Button,
+                        Node {
+                            width: Val::Px(220.),
+                            padding: UiRect::all(Val::Px(5.)),
+                            justify_content: JustifyContent::Center,
+                            ..default()
+                        },
+                        BackgroundColor(BUTTON_COLOR),
+                    ))
+                    .with_child((
+                        Text::new(label),
+                        TextColor(TEXT_COLOR),
+                        TextFont {
+                            font: font.clone(),
+                            font_size: BUTTON_FONT_SIZE,
+                            ..default()
+                        },
+                    ));
+            }
+        });
+}
+
+fn despawn_overlay(mut commands: Commands, overlay_q: Query<Entity, With<OnPauseOverlay>>) {
+    for entity in &overlay_q {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn toggle_pause(
+    input: ActionInput,
+    play_state: Res<State<PlayState>>,
+    mut next_play_state: ResMut<NextState<PlayState>>,
+) {
+    let pause = input.just_pressed(Action::Pause);
+    let back = input.just_pressed(Action::Back);
+    match play_state.get() {
+        PlayState::Running if pause || back => next_play_state.set(PlayState::Paused),
+        PlayState::Paused if pause || back => next_play_state.set(PlayState::Running),
+        PlayState::Settings if back => next_play_state.set(PlayState::Paused),
+        _ => {}
+    }
+}
+
+// Alt-tabbing away mid run shouldn't cost the run
+fn pause_on_focus_lost(
+    mut focus_events: EventReader<WindowFocused>,
+    play_state: Res<State<PlayState>>,
+    mut next_play_state: ResMut<NextState<PlayState>>,
+) {
+    let lost_focus = focus_events.read().any(|event| !event.focused);
+    if lost_focus && *play_state.get() == PlayState::Running {
+        next_play_state.set(PlayState::Paused);
+    }
+}
+
+fn overlay_selection(
+    input: ActionInput,
+    interaction_q: Query<(&Interaction, &OverlayButton), Changed<Interaction>>,
+    button_q: Query<&OverlayButton>,
+    mut selection: ResMut<OverlaySelection>,
+) {
+    let count = button_q.iter().count().max(1);
+    if input.just_pressed(Action::Down) {
+        selection.0 = (selection.0 + 1) % count;
+    }
+    if input.just_pressed(Action::Up) {
+        selection.0 = (selection.0 + count - 1) % count;
+    }
+
+    for (interaction, button) in &interaction_q {
+        if *interaction != Interaction::None {
+            selection.0 = button.index;
+        }
+    }
+}
+
+fn overlay_action(
+    input: ActionInput,
+    button_q: Query<(Ref<Interaction>, &OverlayButton)>,
+    selection: Res<OverlaySelection>,
+    mut activated: EventWriter<OverlayActivated>,
+) {
+    let clicked = button_q
+        .iter()
+        .find(|(interaction, _)| interaction.is_changed() && **interaction == Interaction::Pressed)
+        .map(|(_, button)| button.action);
+    let confirmed = input
+        .just_pressed(Action::Confirm)
+        .then(|| {
+            button_q
+                .iter()
+                .find(|(_, button)| button.index == selection.0)
+                .map(|(_, button)| button.action)
+        })
+        .flatten();
+
+    if let Some(action) = clicked.or(confirmed) {
+        activated.send(OverlayActivated(action));
+    }
+}
+
+// Separate from `overlay_action`, since `ActionInput` already borrows the settings
+fn apply_overlay_action(
+    mut commands: Commands,
+    mut activated: EventReader<OverlayActivated>,
+    mut settings: ResMut<Settings>,
+    mut next_play_state: ResMut<NextState<PlayState>>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    for OverlayActivated(action) in activated.read() {
+        match action {
+            OverlayAction::Resume => next_play_state.set(PlayState::Running),
+            OverlayAction::Restart => {
+                next_play_state.set(PlayState::GetReady);
+                commands.queue(restart_run);
+            }
+            OverlayAction::Settings => next_play_state.set(PlayState::Settings),
+            OverlayAction::QuitToMenu => game_state.set(GameState::Menu),
+            OverlayAction::MusicVolume => {
+                settings.music_volume = next_music_volume(settings.music_volume);
+            }
+            OverlayAction::Ghost => settings.ghost = !settings.ghost,
+            OverlayAction::AttractMode => settings.attract_mode = !settings.attract_mode,
+            OverlayAction::Back => next_play_state.set(PlayState::Paused),
+        }
+    }
+}
+
+/// Steps the volume up by 10%, going back to silent after full volume.
+fn next_music_volume(volume: f32) -> f32 {
+    let step = (volume / MUSIC_VOLUME_STEP).round() + 1.;
+    if step * MUSIC_VOLUME_STEP > 1. + f32::EPSILON {
+        0.
+    } else {
+        step * MUSIC_VOLUME_STEP
+    }
+}
+
+fn update_overlay_buttons(
+    selection: Res<OverlaySelection>,
+    settings: Res<Settings>,
+    mut button_q: Query<(&OverlayButton, &Children, &mut BackgroundColor)>,
+    mut text_q: Query<&mut Text>,
+) {
+    for (button, children, mut background) in button_q.iter_mut() {
+        background.0 = if button.index == selection.0 {
+            SELECTED_BUTTON_COLOR
+        } else {
+            BUTTON_COLOR
+        };
+
+        let label = match button.action {
+            OverlayAction::MusicVolume => {
+                format!("Music: {:.0}%", settings.music_volume * 100.)
+            }
+            OverlayAction::Ghost => {
+                format!("Ghost: {}", if settings.ghost { "On" } else { "Off" })
+            }
+            OverlayAction::AttractMode => {
+                format!("Demo: {}", if settings.attract_mode { "On" } else { "Off" })
+            }
+            _ => continue,
+        };
+        let mut texts = text_q.iter_many_mut(children);
+        while let Some(mut text) = texts.fetch_next() {
+            text.0.clone_from(&label);
+        }
+    }
+}
+
+fn save_settings(mut save_requests: EventWriter<SaveRequested>) {
+    save_requests.send_default();
+}
@@ -0,0 +1,397 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::achievements::UnlockedAchievements;
+use crate::daily::DailyChallenge;
+use crate::difficulty::Preset;
+use crate::leaderboard::{Leaderboard, Leaderboards};
+use crate::settings::Settings;
+use crate::stats::PlayerStats;
+use crate::{GameState, Highscores};
+
+const APP_DIR_NAME: &str = "flappyboi";
+const SAVE_FILE_NAME: &str = "save.ron";
+const BACKUP_FILE_NAME: &str = "save.ron.bak";
+const CORRUPT_FILE_NAME: &str = "save.ron.corrupt";
+// Before the save file existed, the highscore was kept as a bare number in this file
+const LEGACY_HIGHSCORE_FILE_NAME: &str = "highscore.txt";
+
+/// Bump this whenever the layout of `SaveData` changes, and teach `migrate` how to upgrade.
+pub const SAVE_VERSION: u32 = 2;
+
+/// Setting this points the save directory somewhere else, handy for tests and throwaway runs.
+pub const DATA_DIR_ENV: &str = "FLAPPYBOI_DATA_DIR";
+
+pub struct PersistencePlugin;
+
+impl Plugin for PersistencePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SaveRequested>()
+            .add_systems(PreStartup, load_save)
+            .add_systems(OnEnter(GameState::DeathScreen), request_save)
+            .add_systems(Last, write_save.run_if(on_event::<SaveRequested>));
+    }
+}
+
+/// Send this from anywhere that changed persisted state; the save is written once at the end
+/// of the frame no matter how many requests came in.
+#[derive(Default, Event)]
+pub struct SaveRequested;
+
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct SaveData {
+    pub version: u32,
+    pub preset: Preset,
+    pub highscores: BTreeMap<Preset, usize>,
+    pub settings: Settings,
+    pub stats: PlayerStats,
+    pub leaderboards: BTreeMap<Preset, Leaderboard>,
+    pub achievements: UnlockedAchievements,
+    pub daily: DailyChallenge,
+    // Version 1 had a single highscore and leaderboard, `migrate` files them under Normal
+    #[serde(rename = "highscore", skip_serializing)]
+    legacy_highscore: usize,
+    #[serde(rename = "leaderboard", skip_serializing)]
+    legacy_leaderboard: Leaderboard,
+}
+
+/// Everything that ends up in the save file, gathered from the world.
+#[derive(SystemParam)]
+struct Persisted<'w> {
+    preset: Res<'w, Preset>,
+    highscores: Res<'w, Highscores>,
+    settings: Res<'w, Settings>,
+    stats: Res<'w, PlayerStats>,
+    leaderboards: Res<'w, Leaderboards>,
+    achievements: Res<'w, UnlockedAchievements>,
+    daily: Res<'w, DailyChallenge>,
+}
+
+impl Persisted<'_> {
+    fn to_save_data(&self) -> SaveData {
+        SaveData {
+            version: SAVE_VERSION,
+            preset: *self.preset,
+            highscores: self.highscores.0.clone(),
+            settings: self.settings.clone(),
+            stats: self.stats.clone(),
+            leaderboards: self.leaderboards.0.clone(),
+            achievements: self.achievements.clone(),
+            daily: self.daily.clone(),
+            ..default()
+        }
+    }
+}
+
+fn load_save(mut commands: Commands) {
+    let save = load();
+
+    commands.insert_resource(save.preset);
+    commands.insert_resource(Highscores(save.highscores));
+    commands.insert_resource(save.settings);
+    commands.insert_resource(save.stats);
+    commands.insert_resource(Leaderboards(save.leaderboards));
+    commands.insert_resource(save.achievements);
+    commands.insert_resource(save.daily);
+}
+
+fn request_save(mut save_requests: EventWriter<SaveRequested>) {
+    save_requests.send_default();
+}
+
+fn write_save(persisted: Persisted, mut save_requests: EventReader<SaveRequested>) {
+    save_requests.clear();
+    store(&persisted.to_save_data());
+}
+
+/// Directory where everything we persist lives, or `None` if the platform gives us nowhere to
+/// put it.
+pub fn data_dir() -> Option<PathBuf> {
+    if let Some(path) = env::var_os(DATA_DIR_ENV).filter(|path| !path.is_empty()) {
+        return Some(PathBuf::from(path));
+    }
+
+    platform_data_dir().map(|dir| dir.join(APP_DIR_NAME))
+}
+
+#[cfg(target_os = "windows")]
+fn platform_data_dir() -> Option<PathBuf> {
+    env::var_os("LOCALAPPDATA")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+}
+
+#[cfg(target_os = "macos")]
+fn platform_data_dir() -> Option<PathBuf> {
+    home_dir().map(|home| home.join("Library").join("Application Support"))
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+fn platform_data_dir() -> Option<PathBuf> {
+    // The XDG spec says relative paths must be ignored
+    env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| home_dir().map(|home| home.join(".local").join("share")))
+}
+
+#[cfg(not(target_os = "windows"))]
+fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Like `data_dir`, but makes sure the directory exists.
+fn ensure_data_dir() -> Option<PathBuf> {
+    let Some(dir) = data_dir() else {
+        error!("Could not find a data directory to keep the save in");
+        return None;
+    };
+
+    if let Err(e) = fs::create_dir_all(&dir) {
+        error!("Failed to create data directory {}: {}", dir.display(), e);
+        return None;
+    }
+
+    Some(dir)
+}
+
+/// Reads the save from disk, falling back to the backup and then the legacy highscore file.
+/// Never fails, the worst case is a fresh save.
+pub fn load() -> SaveData {
+    let Some(dir) = ensure_data_dir() else {
+        return SaveData::default();
+    };
+    load_from(&dir)
+}
+
+fn load_from(dir: &Path) -> SaveData {
+    let save_path = dir.join(SAVE_FILE_NAME);
+    let backup_path = dir.join(BACKUP_FILE_NAME);
+
+    match read_save(&save_path) {
+        Ok(Some(save)) => return migrate(save),
+        Ok(None) => {}
+        Err(e) => {
+            error!("Save file {} is unreadable: {}", save_path.display(), e);
+            // Keep the broken file around so nothing is lost for good
+            if let Err(e) = fs::rename(&save_path, dir.join(CORRUPT_FILE_NAME)) {
+                error!("Failed to move the corrupt save out of the way: {}", e);
+            }
+        }
+    }
+
+    match read_save(&backup_path) {
+        Ok(Some(save)) => {
+            warn!("Restoring save from backup {}", backup_path.display());
+            if let Err(e) = fs::copy(&backup_path, &save_path) {
+                error!("Failed to restore the backup save: {}", e);
+            }
+            return migrate(save);
+        }
+        Ok(None) => {}
+        Err(e) => error!("Backup save {} is unreadable: {}", backup_path.display(), e),
+    }
+
+    if let Some(highscore) = read_legacy_highscore(&dir.join(LEGACY_HIGHSCORE_FILE_NAME)) {
+        info!(
+            "Migrating highscore {} from {}",
+            highscore, LEGACY_HIGHSCORE_FILE_NAME
+        );
+        let save = SaveData {
+            version: SAVE_VERSION,
+            highscores: BTreeMap::from([(Preset::Normal, highscore)]),
+            ..default()
+        };
+        if let Err(e) = write_save_file(dir, &save) {
+            error!("Failed to write save: {}", e);
+        }
+        return save;
+    }
+
+    SaveData {
+        version: SAVE_VERSION,
+        ..default()
+    }
+}
+
+/// Writes the save atomically, the previous save is kept as a backup.
+pub fn store(save: &SaveData) {
+    let Some(dir) = ensure_data_dir() else {
+        return;
+    };
+
+    if let Err(e) = write_save_file(&dir, save) {
+        error!("Failed to write save: {}", e);
+    }
+}
+
+fn write_save_file(dir: &Path, save: &SaveData) -> io::Result<()> {
+    let contents = ron::ser::to_string_pretty(save, ron::ser::PrettyConfig::default())
+        .map_err(io::Error::other)?;
+
+    let save_path = dir.join(SAVE_FILE_NAME);
+    if save_path.exists() {
+        fs::copy(&save_path, dir.join(BACKUP_FILE_NAME))?;
+    }
+
+    write_atomically(&save_path, &contents)
+}
+
+/// Writes everything to a temp file next to `path` first, so a crash halfway never leaves
+/// it truncated.
+pub fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    let mut file = File::create(&temp_path)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+
+    fs::rename(&temp_path, path)
+}
+
+/// `Ok(None)` if there is no save, `Err` if there is one but it can't be used.
+fn read_save(path: &Path) -> io::Result<Option<SaveData>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    ron::from_str(&contents)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn read_legacy_highscore(path: &Path) -> Option<usize> {
+    let contents = fs::read_to_string(path).ok()?;
+
+    match contents.trim().parse() {
+        Ok(highscore) => Some(highscore),
+        Err(e) => {
+            error!(
+                "Legacy highscore file {} is unreadable: {}",
+                path.display(),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Upgrades a save written by an older version of the game.
+fn migrate(mut save: SaveData) -> SaveData {
+    if save.version > SAVE_VERSION {
+        warn!(
+            "Save file is version {}, newer than the supported {}, unknown fields are dropped",
+            save.version, SAVE_VERSION
+        );
+    }
+
+    if save.version < 2 {
+        // Everything was played on what is now the Normal preset
+        save.highscores
+            .insert(Preset::Normal, std::mem::take(&mut save.legacy_highscore));
+        save.leaderboards
+            .insert(Preset::Normal, std::mem::take(&mut save.legacy_leaderboard));
+    }
+
+    save.version = SAVE_VERSION;
+    save
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory per test, so they can run in parallel.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("flappyboi-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn save_with_highscore(highscore: usize) -> String {
+        let save = SaveData {
+            version: SAVE_VERSION,
+            highscores: BTreeMap::from([(Preset::Normal, highscore)]),
+            ..default()
+        };
+        ron::ser::to_string_pretty(&save, ron::ser::PrettyConfig::default()).unwrap()
+    }
+
+    #[test]
+    fn migrates_legacy_highscore() {
+        let dir = test_dir("legacy");
+        fs::write(dir.join(LEGACY_HIGHSCORE_FILE_NAME), "42\n").unwrap();
+
+        let save = load_from(&dir);
+
+        assert_eq!(save.version, SAVE_VERSION);
+        assert_eq!(save.highscores.get(&Preset::Normal), Some(&42));
+        let stored = read_save(&dir.join(SAVE_FILE_NAME)).unwrap().unwrap();
+        assert_eq!(stored.highscores.get(&Preset::Normal), Some(&42));
+    }
+
+    #[test]
+    fn restores_backup_when_save_is_corrupt() {
+        let dir = test_dir("backup");
+        fs::write(dir.join(SAVE_FILE_NAME), "not ron at all").unwrap();
+        fs::write(dir.join(BACKUP_FILE_NAME), save_with_highscore(7)).unwrap();
+
+        let save = load_from(&dir);
+
+        assert_eq!(save.highscores.get(&Preset::Normal), Some(&7));
+        assert_eq!(
+            fs::read_to_string(dir.join(CORRUPT_FILE_NAME)).unwrap(),
+            "not ron at all"
+        );
+        let restored = read_save(&dir.join(SAVE_FILE_NAME)).unwrap().unwrap();
+        assert_eq!(restored.highscores.get(&Preset::Normal), Some(&7));
+    }
+
+    #[test]
+    fn starts_fresh_when_save_and_backup_are_corrupt() {
+        let dir = test_dir("corrupt");
+        fs::write(dir.join(SAVE_FILE_NAME), "not ron at all").unwrap();
+        fs::write(dir.join(BACKUP_FILE_NAME), "(highscores: {").unwrap();
+
+        let save = load_from(&dir);
+
+        assert_eq!(save.version, SAVE_VERSION);
+        assert!(save.highscores.is_empty());
+        assert!(dir.join(CORRUPT_FILE_NAME).exists());
+        // The broken backup is left alone for whoever wants to dig it out
+        assert!(dir.join(BACKUP_FILE_NAME).exists());
+    }
+
+    #[test]
+    fn keeps_previous_save_as_backup() {
+        let dir = test_dir("store");
+        fs::write(dir.join(SAVE_FILE_NAME), save_with_highscore(3)).unwrap();
+
+        let save = SaveData {
+            version: SAVE_VERSION,
+            highscores: BTreeMap::from([(Preset::Normal, 9)]),
+            ..default()
+        };
+        write_save_file(&dir, &save).unwrap();
+
+        assert_eq!(load_from(&dir).highscores.get(&Preset::Normal), Some(&9));
+        let backup = read_save(&dir.join(BACKUP_FILE_NAME)).unwrap().unwrap();
+        assert_eq!(backup.highscores.get(&Preset::Normal), Some(&3));
+        assert!(!dir.join("save.ron.tmp").exists());
+    }
+}
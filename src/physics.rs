@@ -0,0 +1,69 @@
+use bevy::prelude::*;
+
+/// Ticks per second of the fixed timestep everything that decides a run happens on.
+pub const TICK_RATE: f64 = 60.;
+
+pub struct PhysicsPlugin;
+
+impl Plugin for PhysicsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Time::<Fixed>::from_hz(TICK_RATE))
+            .configure_sets(FixedUpdate, (PhysicsSet::Move, PhysicsSet::Collide).chain())
+            .add_systems(FixedFirst, remember_previous_positions)
+            .add_systems(
+                PostUpdate,
+                interpolate_transforms.before(TransformSystem::TransformPropagate),
+            );
+    }
+}
+
+/// Steps of a fixed tick, in order. Everything in a step sees the previous step's results,
+/// so the same flaps always play out the same way.
+#[derive(SystemSet, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum PhysicsSet {
+    /// Bird and pipes move.
+    Move,
+    /// Bounds, scoring and collisions are checked against where things ended up.
+    Collide,
+}
+
+/// Where an entity is in the simulation. Its `Transform` only follows along, blended
+/// between the last two ticks so movement looks smooth at any frame rate.
+#[derive(Component, Clone, Copy, Default, Debug)]
+pub struct Position {
+    pub current: Vec2,
+    previous: Vec2,
+}
+
+impl Position {
+    pub fn new(at: Vec2) -> Self {
+        Self {
+            current: at,
+            previous: at,
+        }
+    }
+
+    /// Moves without blending from the old spot, for wrapping around and the like.
+    pub fn teleport(&mut self, to: Vec2) {
+        self.current = to;
+        self.previous = to;
+    }
+}
+
+fn remember_previous_positions(mut position_q: Query<&mut Position>) {
+    for mut position in position_q.iter_mut() {
+        position.previous = position.current;
+    }
+}
+
+fn interpolate_transforms(
+    fixed_time: Res<Time<Fixed>>,
+    mut position_q: Query<(&Position, &mut Transform)>,
+) {
+    let t = fixed_time.overstep_fraction();
+    for (position, mut transform) in position_q.iter_mut() {
+        let blended = position.previous.lerp(position.current, t);
+        transform.translation.x = blended.x;
+        transform.translation.y = blended.y;
+    }
+}
@@ -1,6 +1,10 @@
-use super::{despawn_screen, GameState};
-use bevy::asset::embedded_asset;
-use bevy::math::bounding::Aabb2d;
+use super::{despawn_screen, GameState, PlayState};
+use crate::difficulty::{Difficulty, DifficultySettings};
+use crate::game::{start_run, GameRng};
+use crate::physics::{PhysicsSet, Position};
+use crate::player::BIRD_X;
+use crate::tuning::{GameTuning, PipeTuning};
+use bevy::math::bounding::{Aabb2d, BoundingCircle, IntersectsVolume};
 use bevy::prelude::*;
 use bevy::sprite::Anchor::TopCenter;
 use rand::Rng;
@@ -14,29 +18,52 @@ pub struct Pipe {
     pub passed: bool,
 }
 
+/// The hole between the two pipes of a pair.
+#[derive(Clone, Copy, Debug)]
+pub struct Opening {
+    pub x: f32,
+    pub bottom: f32,
+    pub top: f32,
+}
+
+impl Opening {
+    /// Whether a bird's hitbox touches either pipe of the pair, the same way collisions are
+    /// checked in the game.
+    pub fn hits(&self, bird: BoundingCircle, pipes: &PipeTuning) -> bool {
+        let bottom = Position::new(Vec2::new(self.x, self.bottom));
+        let top = Position::new(Vec2::new(self.x, self.top));
+        bird.intersects(&pipe_to_aabb2d(&bottom, false, pipes))
+            || bird.intersects(&pipe_to_aabb2d(&top, true, pipes))
+    }
+}
+
 impl Plugin for PipesPlugin {
     fn build(&self, app: &mut App) {
-        embedded_asset!(app, "../assets/pipe.png");
-        app.add_systems(OnEnter(GameState::Game), spawn_pipes)
+        app.add_systems(OnEnter(GameState::Game), spawn_pipes.after(start_run))
             .add_systems(OnExit(GameState::Game), despawn_screen::<Pipe>)
-            .add_systems(Update, move_pipes.run_if(in_state(GameState::Game)));
+            .add_systems(
+                FixedUpdate,
+                move_pipes
+                    .in_set(PhysicsSet::Move)
+                    .run_if(in_state(PlayState::Running)),
+            );
     }
 }
 
-const PIPE_OPENING: f32 = 120.;
-const PIPE_GAP: f32 = 250.;
-const PIPE_SPEED: f32 = 150.;
-const PIPE_Y_RANGE_MIN: i32 = 70;
-const PIPE_Y_RANGE_MAX: i32 = 300;
-const PIPE_WIDTH: f32 = 52.0; // Width of the pipe sprite
-const PIPE_HEIGHT: f32 = 320.0; // Height of the pipe sprite
-
-fn spawn_pipes(mut commands: Commands, asset_server: Res<AssetServer>) {
-    let mut random = rand::thread_rng();
-
-    for i in 0..5 {
-        let rand_y = random.gen_range(PIPE_Y_RANGE_MIN..=PIPE_Y_RANGE_MAX) as f32;
-        let (top_y, bot_y) = get_pipe_y(rand_y);
+fn spawn_pipes(
+    mut commands: Commands,
+    difficulty_settings: DifficultySettings,
+    tuning: Res<GameTuning>,
+    mut rng: ResMut<GameRng>,
+) {
+    // Every run starts out easy, whatever the last one ended at
+    let difficulty = difficulty_settings.at(0);
+    let mut last_y = None;
+
+    for i in 0..tuning.pipes.count {
+        let rand_y = roll_opening_y(&mut rng, &difficulty, last_y);
+        last_y = Some(rand_y);
+        let (top_y, bot_y) = get_pipe_y(rand_y, difficulty.opening);
 
         commands.spawn((
             Pipe {
@@ -44,12 +71,15 @@ fn spawn_pipes(mut commands: Commands, asset_server: Res<AssetServer>) {
                 passed: false,
             },
             Sprite {
-                image: asset_server.load("embedded://flappyboi/../assets/pipe.png"),
                 anchor: TopCenter,
                 ..default()
             },
+            Position::new(Vec2::new(
+                tuning.pipes.first_x + (i as f32 * difficulty.gap),
+                bot_y,
+            )),
             Transform {
-                translation: Vec3::new(400. + (i as f32 * PIPE_GAP), bot_y, 1.),
+                translation: Vec3::new(0., 0., 1.),
                 ..default()
             },
         ));
@@ -59,12 +89,15 @@ fn spawn_pipes(mut commands: Commands, asset_server: Res<AssetServer>) {
                 passed: false,
             },
             Sprite {
-                image: asset_server.load("embedded://flappyboi/../assets/pipe.png"),
                 anchor: TopCenter,
                 ..default()
             },
+            Position::new(Vec2::new(
+                tuning.pipes.first_x + (i as f32 * difficulty.gap),
+                top_y,
+            )),
             Transform {
-                translation: Vec3::new(400. + (i as f32 * PIPE_GAP), top_y, 1.),
+                translation: Vec3::new(0., 0., 1.),
                 scale: Vec3::new(1., -1., 1.),
                 ..default()
             },
@@ -72,58 +105,112 @@ fn spawn_pipes(mut commands: Commands, asset_server: Res<AssetServer>) {
     }
 }
 
-fn move_pipes(mut pipe_q: Query<(&mut Transform, &mut Pipe)>, time: Res<Time>) {
-    let mut random = rand::thread_rng();
-    let this_loops_random_y = random.gen_range(PIPE_Y_RANGE_MIN..PIPE_Y_RANGE_MAX) as f32;
+fn move_pipes(
+    mut pipe_q: Query<(&mut Position, &mut Pipe)>,
+    difficulty: Res<Difficulty>,
+    tuning: Res<GameTuning>,
+    mut rng: ResMut<GameRng>,
+    time: Res<Time>,
+) {
+    // Both pipes of a pair wrap around on the same tick and share one height, which is only
+    // rolled when that happens so the sequence only depends on the seed
+    let mut wrapped_y = None;
     let mut end_spawn = 0.;
+    let mut last_y = None;
 
     if let Some(last) = pipe_q
         .iter()
-        .max_by(|&x, &y| x.0.translation.x.total_cmp(&y.0.translation.x))
+        .max_by(|&x, &y| x.0.current.x.total_cmp(&y.0.current.x))
     {
-        let transform = last.0;
-        end_spawn = transform.translation.x + 250.;
+        let last_x = last.0.current.x;
+        end_spawn = last_x + difficulty.gap;
+        // The two pipes of the last pair are as far above and below the middle of its opening
+        let pair: Vec<f32> = pipe_q
+            .iter()
+            .filter(|(position, _)| position.current.x == last_x)
+            .map(|(position, _)| position.current.y)
+            .collect();
+        last_y = Some(pair.iter().sum::<f32>() / pair.len() as f32);
     }
-    for (mut transform, mut pipe) in pipe_q.iter_mut() {
-        transform.translation.x -= PIPE_SPEED * time.delta_secs();
-        if transform.translation.x < -52. {
-            let (top, bottom) = get_pipe_y(this_loops_random_y);
-            transform.translation.x = end_spawn;
-
-            if pipe.flipped {
-                transform.translation.y = top;
-            } else {
-                transform.translation.y = bottom;
-            }
+    for (mut position, mut pipe) in pipe_q.iter_mut() {
+        position.current.x -= difficulty.speed * time.delta_secs();
+        if position.current.x < -tuning.pipes.width {
+            let y = *wrapped_y.get_or_insert_with(|| roll_opening_y(&mut rng, &difficulty, last_y));
+            let (top, bottom) = get_pipe_y(y, difficulty.opening);
+            let y = if pipe.flipped { top } else { bottom };
+
+            position.teleport(Vec2::new(end_spawn, y));
             pipe.passed = false;
         }
     }
 }
 
-fn get_pipe_y(y: f32) -> (f32, f32) {
+// The middle of the next opening, close enough to the last one for the bird to get from one
+// to the other
+fn roll_opening_y(rng: &mut GameRng, difficulty: &Difficulty, last_y: Option<f32>) -> f32 {
+    let (mut y_min, mut y_max) = difficulty.y_range;
+    if let Some(last_y) = last_y {
+        // The range may have changed since, with a reloaded tuning
+        let last_y = last_y.clamp(y_min, y_max);
+        y_min = y_min.max(last_y - difficulty.y_shift);
+        y_max = y_max.min(last_y + difficulty.y_shift);
+    }
+    rng.gen_range(y_min..y_max).round()
+}
+
+fn get_pipe_y(y: f32, opening: f32) -> (f32, f32) {
     // y 0 is bottom
     // The top pipe is flipped and has it's anchor at bottom left
     // The bot pipe has its anchor at top left
-    let top = y + PIPE_OPENING / 2.; //600. + y + PIPE_OPENING / 2.;
-    let bottom = y - PIPE_OPENING / 2.; //- y + PIPE_OPENING / 2.;
+    let top = y + opening / 2.; //600. + y + PIPE_OPENING / 2.;
+    let bottom = y - opening / 2.; //- y + PIPE_OPENING / 2.;
 
     (top, bottom)
 }
 
-pub fn pipe_to_aabb2d(transform: &Transform, flipped: bool) -> Aabb2d {
-    // Extract the center position from the transform
-    let center = transform.translation.xy();
+pub fn pipe_to_aabb2d(position: &Position, flipped: bool, pipes: &PipeTuning) -> Aabb2d {
+    let center = position.current;
 
     // Half-size for the AABB is derived from the pipe dimensions
-    let half_size = Vec2::new(PIPE_WIDTH / 2.0, PIPE_HEIGHT / 2.0);
+    let half_size = Vec2::new(pipes.width / 2.0, pipes.height / 2.0);
 
     // Adjust the center based on whether the pipe is flipped
     let adjusted_center = if flipped {
-        center + Vec2::new(0.0, PIPE_HEIGHT / 2.0) // Move center down
+        center + Vec2::new(0.0, pipes.height / 2.0) // Move center down
     } else {
-        center - Vec2::new(0.0, PIPE_HEIGHT / 2.0) // Move center up
+        center - Vec2::new(0.0, pipes.height / 2.0) // Move center up
     };
 
     // Create the AABB
     Aabb2d::new(adjusted_center, half_size)
 }
+
+/// Openings of the pipe pairs a bird still has to get through, nearest first. A pair is
+/// through once it's `reach` behind the bird.
+pub fn openings_ahead<'a>(
+    pipes: impl IntoIterator<Item = (&'a Pipe, &'a Position)>,
+    reach: f32,
+) -> Vec<Opening> {
+    let (tops, bottoms): (Vec<_>, Vec<_>) = pipes
+        .into_iter()
+        .map(|(pipe, position)| (pipe.flipped, position.current))
+        .partition(|(flipped, _)| *flipped);
+
+    let mut openings: Vec<Opening> = bottoms
+        .iter()
+        .filter(|(_, bottom)| bottom.x + reach >= BIRD_X)
+        .filter_map(|(_, bottom)| {
+            // Both pipes of a pair always share their x
+            let (_, top) = tops.iter().min_by(|(_, a), (_, b)| {
+                (a.x - bottom.x).abs().total_cmp(&(b.x - bottom.x).abs())
+            })?;
+            Some(Opening {
+                x: bottom.x,
+                bottom: bottom.y,
+                top: top.y,
+            })
+        })
+        .collect();
+    openings.sort_by(|a, b| a.x.total_cmp(&b.x));
+    openings
+}
@@ -1,187 +1,331 @@
 use super::Score;
-use crate::game::{DeathSound, FlopSound, WohoSound};
+use crate::difficulty::Difficulty;
+use crate::game::{CurrentRun, GameMode};
 use crate::input::JumpEvent;
+use crate::physics::{PhysicsSet, Position};
 use crate::pipes::{pipe_to_aabb2d, Pipe};
-use crate::{despawn_screen, input, GameState, Highscore};
-use bevy::asset::embedded_asset;
+use crate::tuning::{BirdTuning, GameTuning};
+use crate::{despawn_screen, GameState, PlayState};
 use bevy::math::bounding::{Aabb2d, BoundingCircle, IntersectsVolume};
 use bevy::prelude::*;
-use rand::Rng;
 
-const GRAVITY: f32 = -600.;
-const MAX_FALL_SPEED: f32 = -800.;
-const PLAYER_JUMP_SPEED: f32 = 300.;
-const PLAYER_SIZE: (f32, f32) = (34., 24.);
-
-const SCREEN_HEIGHT: f32 = 400.;
+pub const SCREEN_HEIGHT: f32 = 400.;
+/// Birds fly in place, the pipes come to them.
+pub const BIRD_X: f32 = 200.;
 #[derive(Component, Default)]
 #[require(Sprite)]
 pub struct Bird {
+    flight: Flight,
+    /// Set to make the bird flap on the next tick.
+    flapping: bool,
+}
+
+/// The bird the player's flaps go to. Every other bird is flown by something else.
+#[derive(Component)]
+pub struct Player;
+
+/// How a bird is moving, stepped once per fixed tick.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Flight {
     speed: f32,
     angle: f32,
 }
 
+/// Marks a bird that has crashed, it no longer moves, scores or collides.
+#[derive(Component)]
+pub struct Dead;
+
+/// Query filter for birds that are still in the air.
+type Alive = (With<Bird>, Without<Dead>);
+
+/// Run condition for things that stop once the bird has crashed.
+pub fn bird_alive(bird_q: Query<(), Alive>) -> bool {
+    !bird_q.is_empty()
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DeathCause {
+    Ground,
+    Pipe,
+}
+
+/// Sent every time a pipe pair is passed, with the score it brought the run to.
+#[derive(Event)]
+pub struct ScoreEvent {
+    pub score: usize,
+}
+
+/// Sent for every flap a bird actually makes.
+#[derive(Event)]
+pub struct FlapEvent {
+    pub bird: Entity,
+}
+
+/// Sent for every bird that crashes.
+#[derive(Event)]
+pub struct DeathEvent {
+    pub bird: Entity,
+    pub cause: DeathCause,
+}
+
 pub struct PlayerPlugin;
 
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
-        embedded_asset!(app, "../assets/bird.png");
-        app.add_plugins(input::InputPlugin)
+        app.add_event::<JumpEvent>()
+            .add_event::<ScoreEvent>()
+            .add_event::<FlapEvent>()
+            .add_event::<DeathEvent>()
             .add_systems(
-                Update,
+                FixedUpdate,
                 (
-                    jump,
-                    check_bounds,
-                    give_score_for_passing,
-                    check_pipe_collision,
+                    jump.in_set(PhysicsSet::Move),
+                    (
+                        check_bounds,
+                        give_score_for_passing,
+                        check_pipe_collision,
+                        // Evolution carries on with the next generation instead
+                        end_run
+                            .run_if(not(bird_alive).and(not(resource_equals(GameMode::Evolution)))),
+                    )
+                        .chain()
+                        .in_set(PhysicsSet::Collide),
                 )
-                    .run_if(in_state(GameState::Game)),
+                    .run_if(in_state(PlayState::Running)),
+            )
+            .add_systems(
+                FixedUpdate,
+                (start_flying.run_if(on_event::<JumpEvent>), hover)
+                    .chain()
+                    .in_set(PhysicsSet::Move)
+                    .run_if(in_state(PlayState::GetReady)),
             )
             // This would need to check on GameState?
-            .add_systems(OnEnter(GameState::Game), spawn_player)
+            .add_systems(
+                OnEnter(GameState::Game),
+                spawn_player.run_if(
+                    not(resource_equals(GameMode::Evolution))
+                        .and(not(resource_equals(GameMode::Versus))),
+                ),
+            )
             .add_systems(OnExit(GameState::Game), despawn_screen::<Bird>);
     }
 }
 
-fn spawn_player(mut commands: Commands, asset_server: Res<AssetServer>) {
+// Only what the simulation needs, the scenery plugin gives it a sprite
+fn spawn_player(mut commands: Commands) {
     commands.spawn((
         Bird::default(),
-        Sprite {
-            image: asset_server.load("embedded://flappyboi/../assets/bird.png"),
-            ..default()
-        },
+        Player,
+        Position::new(Vec2::new(BIRD_X, SCREEN_HEIGHT / 2.)),
         Transform {
-            translation: Vec3::new(200., SCREEN_HEIGHT / 2., 5.),
+            translation: Vec3::new(BIRD_X, SCREEN_HEIGHT / 2., 5.),
             ..default()
         },
     ));
 }
 
+// The bird bobs up and down until the first flap. The bob follows the ticks spent hovering,
+// so the height the run starts from only depends on when the flap came in.
+fn hover(
+    time: Res<Time>,
+    tuning: Res<GameTuning>,
+    next_play_state: Res<NextState<PlayState>>,
+    mut run: ResMut<CurrentRun>,
+    mut bird_q: Query<&mut Position, Alive>,
+) {
+    // Stay put for any ticks left before the run actually starts
+    if matches!(*next_play_state, NextState::Pending(PlayState::Running)) {
+        return;
+    }
+
+    for mut position in bird_q.iter_mut() {
+        position.current.y = hover_height(run.ready_ticks, time.delta_secs(), &tuning.bird);
+    }
+    run.ready_ticks += 1;
+}
+
+/// Height of a bird that has been hovering for this many fixed ticks of `dt` seconds.
+pub fn hover_height(ready_ticks: u64, dt: f32, tuning: &BirdTuning) -> f32 {
+    let elapsed = ready_ticks as f32 * dt;
+    SCREEN_HEIGHT / 2. + (elapsed * tuning.hover_speed).sin() * tuning.hover_amplitude
+}
+
+// The flap that ends getting ready is still around for `jump` on the next fixed tick, so
+// the run starts with it
+fn start_flying(mut play_state: ResMut<NextState<PlayState>>) {
+    play_state.set(PlayState::Running);
+}
+
 fn check_bounds(
-    mut bird_q: Query<(&mut Transform, &mut Bird)>,
-    mut game_state: ResMut<NextState<GameState>>,
+    bird_q: Query<(Entity, &Position, &Transform), Alive>,
     mut commands: Commands,
-    death_sound: Res<DeathSound>,
+    mut death_events: EventWriter<DeathEvent>,
 ) {
-    let (mut transform, mut bird) = bird_q.single_mut();
-    if (transform.translation.y - transform.scale.y / 2.) <= 0. {
-        game_state.set(GameState::DeathScreen);
-        let mut rng = rand::thread_rng();
-        commands.spawn((
-            AudioPlayer(death_sound.clone()),
-            PlaybackSettings {
-                mode: bevy::audio::PlaybackMode::Despawn,
-                speed: rng.gen_range(0.95..=1.05),
-                ..PlaybackSettings::ONCE
-            },
-        ));
-        reset_bird(&mut bird, &mut transform);
+    for (entity, position, transform) in bird_q.iter() {
+        if (position.current.y - transform.scale.y / 2.) <= 0. {
+            commands.entity(entity).insert(Dead);
+            death_events.send(DeathEvent {
+                bird: entity,
+                cause: DeathCause::Ground,
+            });
+        }
     }
 }
 
+// The run is over once the last bird is down
+fn end_run(mut game_state: ResMut<NextState<GameState>>) {
+    game_state.set(GameState::DeathScreen);
+}
+
 fn jump(
     time: Res<Time>,
     mut jump_events: EventReader<JumpEvent>,
-    bird_q: Single<(&mut Bird, &mut Transform)>,
-    mut commands: Commands,
-    flop_sound: Res<FlopSound>,
+    mut flap_events: EventWriter<FlapEvent>,
+    mut bird_q: Query<(Entity, &mut Bird, &mut Position, &mut Transform), Without<Dead>>,
+    player_q: Query<(), With<Player>>,
+    difficulty: Res<Difficulty>,
+    tuning: Res<GameTuning>,
 ) {
-    let dt = time.delta_secs();
-    let jumped = !jump_events.is_empty();
+    let player_flapped = !jump_events.is_empty();
     jump_events.clear();
-    let (mut bird, mut transform) = bird_q.into_inner();
-
-    if jumped {
-        bird.speed = PLAYER_JUMP_SPEED;
-        let mut rng = rand::thread_rng();
-        commands.spawn((
-            AudioPlayer(flop_sound.clone()),
-            PlaybackSettings {
-                mode: bevy::audio::PlaybackMode::Despawn,
-                speed: rng.gen_range(0.95..=1.05),
-                ..PlaybackSettings::ONCE
-            },
-        ));
-    } else {
-        bird.speed += GRAVITY * dt;
-        bird.speed = bird.speed.max(MAX_FALL_SPEED);
+    for (entity, mut bird, mut position, mut transform) in bird_q.iter_mut() {
+        let is_player = player_q.contains(entity);
+        let flapped = std::mem::take(&mut bird.flapping) || (is_player && player_flapped);
+        if flapped {
+            flap_events.send(FlapEvent { bird: entity });
+        }
+        bird.flight.step(
+            flapped,
+            time.delta_secs(),
+            &difficulty,
+            &tuning.bird,
+            &mut position,
+            &mut transform,
+        );
+    }
+}
+
+impl Bird {
+    /// Vertical speed, positive while rising.
+    pub fn speed(&self) -> f32 {
+        self.flight.speed
+    }
+
+    /// How the bird is moving, to try out flaps on a copy of it.
+    pub fn flight(&self) -> Flight {
+        self.flight
     }
-    transform.translation.y += bird.speed * dt;
 
-    if (transform.translation.y - transform.scale.y / 2.) > SCREEN_HEIGHT {
-        bird.speed = 0.0;
+    /// Makes the bird flap on the next tick, for birds the player doesn't fly.
+    pub fn flap(&mut self) {
+        self.flapping = true;
     }
-    transform.translation.y = transform.translation.y.clamp(0., SCREEN_HEIGHT);
-
-    // Set bird rotation based on speed.
-    if bird.speed > 0.0 {
-        // Rotate left.
-        bird.angle += 600.0 * dt;
-    } else if bird.speed < -110.0 {
-        // Rotate right.
-        bird.angle -= 480.0 * dt;
+}
+
+impl Flight {
+    /// Vertical speed, positive while rising.
+    pub fn speed(&self) -> f32 {
+        self.speed
     }
-    bird.angle = bird.angle.clamp(-90.0, 30.0);
-    transform.rotation = Quat::from_rotation_z(bird.angle.to_radians());
 
-    // // DEBUG
-    // gizmos.rect_2d(
-    //     Isometry2d::new(
-    //         transform.translation.truncate(),
-    //         Rot2::from(bird.angle),
-    //     ),
-    //     Vec2::from(PLAYER_SIZE),
-    //     Color::srgb(1., 1., 1.));
+    pub fn step(
+        &mut self,
+        flapped: bool,
+        dt: f32,
+        difficulty: &Difficulty,
+        tuning: &BirdTuning,
+        position: &mut Position,
+        transform: &mut Transform,
+    ) {
+        if flapped {
+            self.speed = difficulty.jump_speed;
+        } else {
+            self.speed += difficulty.gravity * dt;
+            self.speed = self.speed.max(tuning.max_fall_speed);
+        }
+        position.current.y += self.speed * dt;
+
+        if (position.current.y - transform.scale.y / 2.) > SCREEN_HEIGHT {
+            self.speed = 0.0;
+        }
+        position.current.y = position.current.y.clamp(0., SCREEN_HEIGHT);
+
+        // Set bird rotation based on speed.
+        if self.speed > 0.0 {
+            // Rotate left.
+            self.angle += tuning.rise_rotation_speed * dt;
+        } else if self.speed < tuning.fall_rotation_threshold {
+            // Rotate right.
+            self.angle -= tuning.fall_rotation_speed * dt;
+        }
+        self.angle = self
+            .angle
+            .clamp(tuning.max_fall_angle, tuning.max_rise_angle);
+        transform.rotation = Quat::from_rotation_z(self.angle.to_radians());
+
+        // // DEBUG
+        // gizmos.rect_2d(
+        //     Isometry2d::new(
+        //         transform.translation.truncate(),
+        //         Rot2::from(self.angle),
+        //     ),
+        //     Vec2::from(tuning.size),
+        //     Color::srgb(1., 1., 1.));
+    }
 }
 
 fn give_score_for_passing(
     mut score: ResMut<Score>,
-    mut highscore: ResMut<Highscore>,
-    bird_q: Single<&Transform, (With<Bird>, Without<Pipe>)>,
-    mut pipes_q: Query<(&mut Pipe, &mut Transform)>,
-    mut commands: Commands,
-    woho_sound: Res<WohoSound>,
+    bird_q: Query<&Position, (Alive, Without<Pipe>)>,
+    mut pipes_q: Query<(&mut Pipe, &Position)>,
+    mut score_events: EventWriter<ScoreEvent>,
 ) {
-    let bird_transform = bird_q.into_inner();
+    // Every bird flies at the same x, so one still in the air passes a pipe for all of them
+    let Some(bird_position) = bird_q.iter().next() else {
+        return;
+    };
 
-    for (mut pipe, transform) in pipes_q.iter_mut() {
+    for (mut pipe, position) in pipes_q.iter_mut() {
         // Prevent giving score every tick once we pass a pipe
         if pipe.passed {
             continue;
         }
-        if transform.translation.x < bird_transform.translation.x - 30. {
+        if position.current.x < bird_position.current.x - 30. {
             pipe.passed = true;
 
             // Only give score for one of the 2 pipes it passes, flipped is unique in the pair
             if pipe.flipped {
                 **score += 1;
-                if score.gt(&highscore) {
-                    **highscore = score.clone();
-                }
-
-                commands.spawn((AudioPlayer(woho_sound.clone()), PlaybackSettings::DESPAWN));
+                score_events.send(ScoreEvent { score: **score });
             }
         }
     }
 }
 
 fn check_pipe_collision(
-    bird_q: Single<(&mut Transform, &mut Bird), Without<Pipe>>,
-    pipes_q: Query<(&Transform, &Pipe), Without<Bird>>,
-    mut game_state: ResMut<NextState<GameState>>,
+    bird_q: Query<(Entity, &Position), (Alive, Without<Pipe>)>,
+    pipes_q: Query<(&Position, &Pipe), Without<Bird>>,
     mut commands: Commands,
-    death_sound: Res<DeathSound>,
+    mut death_events: EventWriter<DeathEvent>,
+    tuning: Res<GameTuning>,
 ) {
-    let (mut bird_transform, mut bird) = bird_q.into_inner();
-    for (pipe_transform, pipe) in pipes_q.iter() {
-        let b_translate = bird_transform.translation.truncate();
-        let bird_circle = BoundingCircle::new(b_translate, (PLAYER_SIZE.1 / 2.) - 1.);
-        let collides = bird_collides(bird_circle, pipe_to_aabb2d(pipe_transform, pipe.flipped));
-
-        if collides {
-            game_state.set(GameState::DeathScreen);
-            commands.spawn((AudioPlayer(death_sound.clone()), PlaybackSettings::DESPAWN));
-            reset_bird(&mut bird, &mut bird_transform);
+    let pipe_boxes: Vec<Aabb2d> = pipes_q
+        .iter()
+        .map(|(pipe_position, pipe)| pipe_to_aabb2d(pipe_position, pipe.flipped, &tuning.pipes))
+        .collect();
+
+    for (entity, bird_position) in bird_q.iter() {
+        let bird_circle = BoundingCircle::new(bird_position.current, tuning.bird.hitbox_radius());
+        if pipe_boxes
+            .iter()
+            .any(|&pipe_box| bird_collides(bird_circle, pipe_box))
+        {
+            commands.entity(entity).insert(Dead);
+            death_events.send(DeathEvent {
+                bird: entity,
+                cause: DeathCause::Pipe,
+            });
         }
     }
 }
@@ -191,10 +335,3 @@ fn check_pipe_collision(
 fn bird_collides(bird: BoundingCircle, bounding_box: Aabb2d) -> bool {
     bird.intersects(&bounding_box)
 }
-
-fn reset_bird(bird: &mut Bird, transform: &mut Transform) {
-    transform.translation.y = SCREEN_HEIGHT / 2.;
-    transform.rotation = Quat::from_rotation_z(0.);
-    bird.speed = 0.;
-    bird.angle = 0.;
-}
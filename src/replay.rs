@@ -0,0 +1,565 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::{despawn_screen, GameState, PlayState, Score};
+use crate::cli::CliArgs;
+use crate::difficulty::{DifficultySettings, Preset};
+use crate::game::{player_playing, CurrentRun, GameMode};
+use crate::ghost::ChosenGhost;
+use crate::input::{Action, ActionInput, JumpEvent};
+use crate::leaderboard::{format_date, unix_timestamp};
+use crate::persistence;
+use crate::physics::PhysicsSet;
+use crate::player::DeathEvent;
+use crate::settings::Settings;
+use crate::tuning::GameTuning;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+const REPLAY_DIR_NAME: &str = "replays";
+// The best replay of every preset is kept in here, so it's never pruned
+const BEST_DIR_NAME: &str = "best";
+// Oldest replays are deleted once there are more than this many
+const MAX_KEPT_REPLAYS: usize = 100;
+// How many of the newest replays the replay screen lists
+const LISTED_REPLAYS: usize = 8;
+
+const TITLE_FONT_SIZE: f32 = 30.0;
+const ROW_FONT_SIZE: f32 = 16.0;
+const TEXT_COLOR: Color = Color::srgb(0.1, 0., 0.);
+const PANEL_COLOR: Color = Color::srgba(1., 1., 1., 0.8);
+const SELECTED_ROW_COLOR: Color = Color::srgb(1.0, 0.7, 0.1);
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(PlaybackPlugin)
+            .init_resource::<Recording>()
+            .init_resource::<ReplaySelection>()
+            .add_systems(OnEnter(GameState::Game), start_recording)
+            .add_systems(
+                FixedUpdate,
+                record_jumps
+                    .in_set(PhysicsSet::Move)
+                    .run_if(in_state(PlayState::Running).and(player_playing)),
+            )
+            .add_systems(
+                PostUpdate,
+                save_recording.run_if(on_event::<DeathEvent>.and(player_playing)),
+            )
+            .add_systems(
+                OnEnter(GameState::Menu),
+                (stop_playback, play_replay_from_args),
+            )
+            .add_systems(OnEnter(GameState::Replays), replays_setup)
+            .add_systems(
+                OnExit(GameState::Replays),
+                despawn_screen::<OnReplaysScreen>,
+            )
+            .add_systems(
+                Update,
+                (
+                    replays_input,
+                    choose_ghost,
+                    update_replay_rows.run_if(
+                        resource_changed::<ReplaySelection>.or(resource_changed::<ChosenGhost>),
+                    ),
+                )
+                    .chain()
+                    .run_if(in_state(GameState::Replays)),
+            );
+    }
+}
+
+/// Flaps for the bird while a `ReplayPlayback` is present. Needs nothing but the gameplay,
+/// so it also drives headless runs.
+pub struct PlaybackPlugin;
+
+impl Plugin for PlaybackPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::Game), restart_playback)
+            .add_systems(
+                FixedUpdate,
+                // Before anything reads flaps, so played back ones land on their tick
+                play_back_jumps.before(PhysicsSet::Move).run_if(
+                    in_state(PlayState::GetReady)
+                        .or(in_state(PlayState::Running))
+                        .and(playing_replay),
+                ),
+            );
+    }
+}
+
+/// Everything needed to play a run again: the pipes come from the seed and preset, the rest
+/// follows from when the bird flapped.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Replay {
+    pub seed: u64,
+    pub preset: Preset,
+    /// `GameTuning::version` when it was recorded, other tuning can play out differently.
+    pub tuning_version: u32,
+    /// Fixed ticks the bird hovered for before the first flap.
+    pub ready_ticks: u64,
+    /// Fixed ticks since the first flap on which the bird flapped, in order.
+    pub jumps: Vec<u64>,
+    /// The fixed tick since the first flap on which the bird crashed.
+    pub ticks: u64,
+    pub score: usize,
+    /// Seconds since the unix epoch when the run ended.
+    pub recorded_at: u64,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> io::Result<Replay> {
+        let contents = fs::read_to_string(path)?;
+        ron::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// The highest scoring replay recorded on the preset, if there is one.
+    pub fn load_best(preset: Preset) -> Option<Replay> {
+        let path = best_path(preset).ok()?;
+        match Replay::load(&path) {
+            Ok(replay) => Some(replay),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => {
+                warn!("Best replay {} is unreadable: {}", path.display(), e);
+                None
+            }
+        }
+    }
+}
+
+/// Flaps of the run in progress, kept until it ends.
+#[derive(Resource, Default)]
+struct Recording {
+    jumps: Vec<u64>,
+}
+
+/// Present while a replay is being watched, `GameMode::Replay` is set alongside it.
+#[derive(Resource)]
+pub struct ReplayPlayback {
+    pub replay: Replay,
+    /// Whether the first flap has been sent yet.
+    started: bool,
+    /// Index into `replay.jumps` of the next flap to send.
+    next_jump: usize,
+}
+
+impl ReplayPlayback {
+    pub fn new(replay: Replay) -> Self {
+        Self {
+            replay,
+            started: false,
+            next_jump: 0,
+        }
+    }
+}
+
+/// Run condition for the systems that drive a replay being watched.
+pub fn playing_replay(mode: Res<GameMode>) -> bool {
+    matches!(*mode, GameMode::Replay(_))
+}
+
+#[derive(Component)]
+struct OnReplaysScreen;
+
+#[derive(Component)]
+struct ReplayRow(usize);
+
+/// The replays listed on the replay screen, newest first, and which one is selected.
+#[derive(Resource, Default)]
+struct ReplaySelection {
+    replays: Vec<Replay>,
+    index: usize,
+}
+
+fn start_recording(mut recording: ResMut<Recording>) {
+    recording.jumps.clear();
+}
+
+// A replay that's watched again starts from the top
+fn restart_playback(playback: Option<ResMut<ReplayPlayback>>) {
+    if let Some(mut playback) = playback {
+        playback.started = false;
+        playback.next_jump = 0;
+    }
+}
+
+fn record_jumps(
+    mut jump_events: EventReader<JumpEvent>,
+    mut recording: ResMut<Recording>,
+    run: Res<CurrentRun>,
+) {
+    // Several flaps in one tick are a single flap to `jump`
+    if !jump_events.is_empty() {
+        jump_events.clear();
+        recording.jumps.push(run.ticks);
+    }
+}
+
+fn play_back_jumps(
+    mut playback: ResMut<ReplayPlayback>,
+    run: Res<CurrentRun>,
+    play_state: Res<State<PlayState>>,
+    mut jump_events: EventWriter<JumpEvent>,
+) {
+    match play_state.get() {
+        PlayState::GetReady => {
+            if !playback.started && run.ready_ticks >= playback.replay.ready_ticks {
+                playback.started = true;
+                jump_events.send_default();
+            }
+        }
+        _ => {
+            let mut flapped = false;
+            while let Some(&tick) = playback.replay.jumps.get(playback.next_jump) {
+                if tick > run.ticks {
+                    break;
+                }
+                playback.next_jump += 1;
+                flapped = true;
+            }
+            if flapped {
+                jump_events.send_default();
+            }
+        }
+    }
+}
+
+fn save_recording(
+    recording: Res<Recording>,
+    run: Res<CurrentRun>,
+    score: Res<Score>,
+    difficulty: DifficultySettings,
+    tuning: Res<GameTuning>,
+) {
+    let Some(seed) = run.seed else {
+        return;
+    };
+
+    let replay = Replay {
+        seed,
+        preset: difficulty.preset(),
+        tuning_version: tuning.version,
+        ready_ticks: run.ready_ticks,
+        jumps: recording.jumps.clone(),
+        ticks: run.ticks,
+        score: **score,
+        recorded_at: unix_timestamp(),
+    };
+    match store(&replay) {
+        Ok(path) => info!("Saved replay to {}", path.display()),
+        Err(e) => error!("Failed to save replay: {}", e),
+    }
+
+    let best_score = Replay::load_best(replay.preset).map(|best| best.score);
+    if best_score.is_none_or(|best_score| replay.score > best_score) {
+        if let Err(e) = store_best(&replay) {
+            error!("Failed to save best replay: {}", e);
+        }
+    }
+}
+
+fn replay_dir() -> io::Result<PathBuf> {
+    let dir = persistence::data_dir()
+        .ok_or_else(|| io::Error::other("no data directory"))?
+        .join(REPLAY_DIR_NAME);
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// Paths of every saved replay, oldest first.
+fn replay_paths(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "ron"))
+        .collect::<Vec<_>>();
+    // Names start with the time they were recorded, so they sort by age
+    paths.sort();
+    Ok(paths)
+}
+
+fn store(replay: &Replay) -> io::Result<PathBuf> {
+    let dir = replay_dir()?;
+    let path = dir.join(format!("{}-{}.ron", replay.recorded_at, replay.seed));
+    let contents = ron::to_string(replay).map_err(io::Error::other)?;
+    persistence::write_atomically(&path, &contents)?;
+
+    let paths = replay_paths(&dir)?;
+    let excess = paths.len().saturating_sub(MAX_KEPT_REPLAYS);
+    for old in &paths[..excess] {
+        if let Err(e) = fs::remove_file(old) {
+            warn!("Failed to remove old replay {}: {}", old.display(), e);
+        }
+    }
+
+    Ok(path)
+}
+
+fn best_path(preset: Preset) -> io::Result<PathBuf> {
+    let dir = replay_dir()?.join(BEST_DIR_NAME);
+    fs::create_dir_all(&dir)?;
+    Ok(dir.join(format!("{}.ron", preset.name().to_lowercase())))
+}
+
+fn store_best(replay: &Replay) -> io::Result<()> {
+    let contents = ron::to_string(replay).map_err(io::Error::other)?;
+    persistence::write_atomically(&best_path(replay.preset)?, &contents)
+}
+
+/// The newest replays on disk, newest first. Unreadable ones are skipped.
+fn newest_replays(count: usize) -> Vec<Replay> {
+    let paths = match replay_dir().and_then(|dir| replay_paths(&dir)) {
+        Ok(paths) => paths,
+        Err(e) => {
+            error!("Failed to list replays: {}", e);
+            return Vec::new();
+        }
+    };
+
+    paths
+        .iter()
+        .rev()
+        .filter_map(|path| match Replay::load(path) {
+            Ok(replay) => Some(replay),
+            Err(e) => {
+                warn!("Skipping unreadable replay {}: {}", path.display(), e);
+                None
+            }
+        })
+        .take(count)
+        .collect()
+}
+
+fn start_playback(
+    commands: &mut Commands,
+    replay: Replay,
+    tuning: &GameTuning,
+    mode: &mut GameMode,
+    game_state: &mut NextState<GameState>,
+) {
+    if replay.tuning_version != tuning.version {
+        warn!(
+            "Replay was recorded with tuning version {}, this is version {}, it may play out differently",
+            replay.tuning_version, tuning.version
+        );
+    }
+    info!(
+        "Playing replay of seed {} on {}, scored {}",
+        replay.seed,
+        replay.preset.name(),
+        replay.score
+    );
+
+    *mode = GameMode::Replay(replay.preset);
+    commands.insert_resource(ReplayPlayback::new(replay));
+    game_state.set(GameState::Game);
+}
+
+fn stop_playback(mut commands: Commands) {
+    commands.remove_resource::<ReplayPlayback>();
+}
+
+// `--replay <file>` plays the file as soon as the menu first shows up
+fn play_replay_from_args(
+    mut commands: Commands,
+    args: Res<CliArgs>,
+    tuning: Res<GameTuning>,
+    mut mode: ResMut<GameMode>,
+    mut game_state: ResMut<NextState<GameState>>,
+    mut done: Local<bool>,
+) {
+    if std::mem::replace(&mut *done, true) {
+        return;
+    }
+    let Some(path) = &args.replay else {
+        return;
+    };
+
+    match Replay::load(path) {
+        Ok(replay) => start_playback(&mut commands, replay, &tuning, &mut mode, &mut game_state),
+        Err(e) => error!("Failed to load replay {}: {}", path.display(), e),
+    }
+}
+
+fn replays_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut selection: ResMut<ReplaySelection>,
+    settings: Res<Settings>,
+) {
+    *selection = ReplaySelection {
+        replays: newest_replays(LISTED_REPLAYS),
+        index: 0,
+    };
+    let font = asset_server.load("embedded://flappyboi/../assets/fonts/FiraSans-Bold.ttf");
+    let row_font = TextFont {
+        font: font.clone(),
+        font_size: ROW_FONT_SIZE,
+        ..default()
+    };
+
+    commands
+        .spawn((
+            OnReplaysScreen,
+            Node {
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                height: Val::Percent(100.),
+                width: Val::Percent(100.),
+                padding: UiRect::all(Val::Px(10.)),
+                row_gap: Val::Px(6.),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new("Replays"),
+                TextColor(TEXT_COLOR),
+                TextFont {
+                    font: font.clone(),
+                    font_size: TITLE_FONT_SIZE,
+                    ..default()
+                },
+            ));
+
+            parent
+                .spawn((
+                    Node {
+                        flex_direction: FlexDirection::Column,
+                        padding: UiRect::all(Val::Px(6.)),
+                        row_gap: Val::Px(2.),
+                        ..default()
+                    },
+                    BackgroundColor(PANEL_COLOR),
+                ))
+                .with_children(|table| {
+                    if selection.replays.is_empty() {
+                        table.spawn((
+                            Text::new("No replays yet, every run is recorded"),
+                            TextColor(TEXT_COLOR),
+                            row_font.clone(),
+                        ));
+                    }
+
+                    for (index, replay) in selection.replays.iter().enumerate() {
+                        table.spawn((
+                            ReplayRow(index),
+                            Button,
+                            Text::new(row_label(replay, false)),
+                            TextColor(TEXT_COLOR),
+                            row_font.clone(),
+                            Node {
+                                width: Val::Px(340.),
+                                padding: UiRect::axes(Val::Px(4.), Val::Px(2.)),
+                                ..default()
+                            },
+                            BackgroundColor(Color::NONE),
+                        ));
+                    }
+                });
+
+            parent.spawn((
+                Text::new(format!(
+                    "{} to watch, {} to race as ghost, {} to go back",
+                    settings.bindings.describe(Action::Confirm),
+                    settings.bindings.describe(Action::Pause),
+                    settings.bindings.describe(Action::Back)
+                )),
+                TextColor(TEXT_COLOR),
+                row_font.clone(),
+            ));
+        });
+}
+
+fn replays_input(
+    mut commands: Commands,
+    input: ActionInput,
+    interaction_q: Query<(&Interaction, &ReplayRow), Changed<Interaction>>,
+    mut selection: ResMut<ReplaySelection>,
+    tuning: Res<GameTuning>,
+    mut mode: ResMut<GameMode>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    if input.just_pressed(Action::Back) {
+        game_state.set(GameState::Menu);
+        return;
+    }
+
+    let count = selection.replays.len().max(1);
+    if input.just_pressed(Action::Down) {
+        selection.index = (selection.index + 1) % count;
+    }
+    if input.just_pressed(Action::Up) {
+        selection.index = (selection.index + count - 1) % count;
+    }
+
+    let mut clicked = false;
+    for (interaction, row) in &interaction_q {
+        match interaction {
+            Interaction::Pressed => {
+                selection.index = row.0;
+                clicked = true;
+            }
+            Interaction::Hovered => selection.index = row.0,
+            Interaction::None => {}
+        }
+    }
+
+    if clicked || input.just_pressed(Action::Confirm) {
+        if let Some(replay) = selection.replays.get(selection.index).cloned() {
+            start_playback(&mut commands, replay, &tuning, &mut mode, &mut game_state);
+        }
+    }
+}
+
+// Picking the ghost's row again goes back to racing the personal best
+fn choose_ghost(
+    input: ActionInput,
+    selection: Res<ReplaySelection>,
+    mut chosen_ghost: ResMut<ChosenGhost>,
+) {
+    if !input.just_pressed(Action::Pause) {
+        return;
+    }
+    let Some(replay) = selection.replays.get(selection.index) else {
+        return;
+    };
+
+    chosen_ghost.0 = if chosen_ghost.0.as_ref() == Some(replay) {
+        None
+    } else {
+        Some(replay.clone())
+    };
+}
+
+fn row_label(replay: &Replay, ghost: bool) -> String {
+    format!(
+        "{}  {}  score {}  seed {}{}",
+        format_date(replay.recorded_at),
+        replay.preset.name(),
+        replay.score,
+        replay.seed,
+        if ghost { "  (ghost)" } else { "" }
+    )
+}
+
+fn update_replay_rows(
+    selection: Res<ReplaySelection>,
+    chosen_ghost: Res<ChosenGhost>,
+    mut row_q: Query<(&ReplayRow, &mut Text, &mut BackgroundColor)>,
+) {
+    for (row, mut text, mut background) in row_q.iter_mut() {
+        background.0 = if row.0 == selection.index {
+            SELECTED_ROW_COLOR
+        } else {
+            Color::NONE
+        };
+
+        if let Some(replay) = selection.replays.get(row.0) {
+            text.0 = row_label(replay, chosen_ghost.0.as_ref() == Some(replay));
+        }
+    }
+}
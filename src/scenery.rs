@@ -0,0 +1,91 @@
+use super::PlayState;
+use crate::difficulty::Difficulty;
+use crate::pipes::Pipe;
+use crate::player::Bird;
+use crate::tuning::GameTuning;
+use bevy::asset::embedded_asset;
+use bevy::prelude::*;
+
+const BACKGROUND_WIDTH: f32 = 400.; //1024.;
+
+/// Everything that's only there to be looked at: the images of the bird and pipes, and the
+/// scrolling background. Nothing in here decides how a run goes.
+pub struct SceneryPlugin;
+
+impl Plugin for SceneryPlugin {
+    fn build(&self, app: &mut App) {
+        embedded_asset!(app, "../assets/bird.png");
+        embedded_asset!(app, "../assets/pipe.png");
+        embedded_asset!(app, "../assets/bg.png");
+
+        app.add_systems(Startup, spawn_background)
+            .add_systems(Update, (dress_birds, dress_pipes))
+            // The background keeps scrolling while getting ready, so the bird looks like
+            // it's flying in place. It decides nothing, so it moves every frame.
+            .add_systems(
+                Update,
+                move_background
+                    .run_if(in_state(PlayState::GetReady).or(in_state(PlayState::Running))),
+            );
+    }
+}
+
+#[derive(Component)]
+struct BackgroundTile;
+
+fn spawn_background(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((
+        BackgroundTile,
+        Sprite {
+            image: asset_server.load("embedded://flappyboi/../assets/bg.png"),
+            custom_size: Some(Vec2::new(400., 400.)),
+            anchor: bevy::sprite::Anchor::BottomLeft,
+            ..default()
+        },
+        Transform {
+            translation: Vec3::new(0., 0., -10.),
+            ..default()
+        },
+    ));
+
+    commands.spawn((
+        BackgroundTile,
+        Sprite {
+            image: asset_server.load("embedded://flappyboi/../assets/bg.png"),
+            custom_size: Some(Vec2::new(400., 400.)),
+            anchor: bevy::sprite::Anchor::BottomLeft,
+            ..default()
+        },
+        Transform {
+            translation: Vec3::new(BACKGROUND_WIDTH, 0., -10.),
+            ..default()
+        },
+    ));
+}
+
+fn dress_birds(mut bird_q: Query<&mut Sprite, Added<Bird>>, asset_server: Res<AssetServer>) {
+    for mut sprite in bird_q.iter_mut() {
+        sprite.image = asset_server.load("embedded://flappyboi/../assets/bird.png");
+    }
+}
+
+fn dress_pipes(mut pipe_q: Query<&mut Sprite, Added<Pipe>>, asset_server: Res<AssetServer>) {
+    for mut sprite in pipe_q.iter_mut() {
+        sprite.image = asset_server.load("embedded://flappyboi/../assets/pipe.png");
+    }
+}
+
+fn move_background(
+    mut background_q: Query<&mut Transform, With<BackgroundTile>>,
+    difficulty: Res<Difficulty>,
+    tuning: Res<GameTuning>,
+    time: Res<Time>,
+) {
+    let speed = difficulty.speed * tuning.background_parallax;
+    for mut transform in background_q.iter_mut() {
+        transform.translation.x -= speed * time.delta_secs();
+        if transform.translation.x < -BACKGROUND_WIDTH {
+            transform.translation.x = BACKGROUND_WIDTH;
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use std::collections::BTreeMap;
+
+use crate::input::{Bindings, GamepadBindings};
+use crate::lan;
+use crate::versus::{self, MAX_PLAYERS};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Resource, Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Settings {
+    pub music_volume: f32,
+    /// Name last typed into the leaderboard, offered again next time.
+    pub player_name: String,
+    pub bindings: Bindings,
+    /// Race a ghost of the best run, or the replay picked for it.
+    pub ghost: bool,
+    /// Let the autopilot fly a demo when the menu is left alone for a while.
+    pub attract_mode: bool,
+    /// How many birds a versus round starts with.
+    pub versus_players: usize,
+    /// Flap key of every versus player, by player.
+    pub versus_keys: [KeyCode; MAX_PLAYERS],
+    /// Address last joined for a LAN race, offered again next time.
+    pub lan_address: String,
+    /// Controller layouts, by the name the controller reports.
+    pub gamepad_profiles: BTreeMap<String, GamepadBindings>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            music_volume: 0.5,
+            player_name: String::new(),
+            bindings: Bindings::default(),
+            ghost: true,
+            attract_mode: true,
+            versus_players: 2,
+            versus_keys: versus::DEFAULT_KEYS,
+            lan_address: format!("127.0.0.1:{}", lan::PORT),
+            gamepad_profiles: BTreeMap::new(),
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use crate::player::{DeathCause, DeathEvent, FlapEvent, Player, ScoreEvent};
+use crate::versus::Contender;
+use bevy::asset::embedded_asset;
+use bevy::audio::PlaybackMode;
+use bevy::prelude::*;
+use rand::Rng;
+
+/// Plays the sound effects for what happens in a run. The gameplay only sends events, so it
+/// runs just the same without any audio.
+pub struct SoundsPlugin;
+
+impl Plugin for SoundsPlugin {
+    fn build(&self, app: &mut App) {
+        embedded_asset!(app, "../assets/audio/flop.ogg");
+        embedded_asset!(app, "../assets/audio/death.ogg");
+        embedded_asset!(app, "../assets/audio/woho.ogg");
+        embedded_asset!(app, "../assets/audio/themesong.ogg");
+
+        app.add_systems(Startup, load_sounds).add_systems(
+            Update,
+            (
+                play_flop.run_if(on_event::<FlapEvent>),
+                play_woho.run_if(on_event::<ScoreEvent>),
+                play_death.run_if(on_event::<DeathEvent>),
+            ),
+        );
+    }
+}
+
+#[derive(Resource)]
+struct Sounds {
+    flop: Handle<AudioSource>,
+    death: Handle<AudioSource>,
+    woho: Handle<AudioSource>,
+}
+
+fn load_sounds(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(Sounds {
+        flop: asset_server.load("embedded://flappyboi/../assets/audio/flop.ogg"),
+        death: asset_server.load("embedded://flappyboi/../assets/audio/death.ogg"),
+        woho: asset_server.load("embedded://flappyboi/../assets/audio/woho.ogg"),
+    });
+}
+
+/// Plays once with the pitch nudged a little, so repeats don't sound canned.
+fn varied(sound: &Handle<AudioSource>) -> impl Bundle {
+    let mut rng = rand::thread_rng();
+    (
+        AudioPlayer(sound.clone()),
+        PlaybackSettings {
+            mode: PlaybackMode::Despawn,
+            speed: rng.gen_range(0.95..=1.05),
+            ..PlaybackSettings::ONCE
+        },
+    )
+}
+
+type FlownByPeople = Or<(With<Player>, With<Contender>)>;
+
+// One sound a tick is plenty, however many birds flapped on it
+fn play_flop(
+    mut commands: Commands,
+    mut flap_events: EventReader<FlapEvent>,
+    player_q: Query<(), FlownByPeople>,
+    sounds: Res<Sounds>,
+) {
+    if flap_events
+        .read()
+        .any(|event| player_q.contains(event.bird))
+    {
+        commands.spawn(varied(&sounds.flop));
+    }
+}
+
+fn play_woho(
+    mut commands: Commands,
+    mut score_events: EventReader<ScoreEvent>,
+    sounds: Res<Sounds>,
+) {
+    for _ in score_events.read() {
+        commands.spawn((AudioPlayer(sounds.woho.clone()), PlaybackSettings::DESPAWN));
+    }
+}
+
+// Only for birds people fly, a whole generation going down at once would be deafening
+fn play_death(
+    mut commands: Commands,
+    mut death_events: EventReader<DeathEvent>,
+    player_q: Query<(), FlownByPeople>,
+    sounds: Res<Sounds>,
+) {
+    for event in death_events.read() {
+        if !player_q.contains(event.bird) {
+            continue;
+        }
+        match event.cause {
+            DeathCause::Ground => commands.spawn(varied(&sounds.death)),
+            DeathCause::Pipe => {
+                commands.spawn((AudioPlayer(sounds.death.clone()), PlaybackSettings::DESPAWN))
+            }
+        };
+    }
+}
@@ -0,0 +1,187 @@
+use super::{despawn_screen, GameState, Score};
+use crate::game::{player_playing, CurrentRun};
+use crate::input::{Action, ActionInput, JumpEvent};
+use crate::player::{DeathCause, DeathEvent};
+use crate::settings::Settings;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+const TITLE_FONT_SIZE: f32 = 30.0;
+const ROW_FONT_SIZE: f32 = 18.0;
+const TEXT_COLOR: Color = Color::srgb(0.1, 0., 0.);
+const VALUE_COLOR: Color = Color::srgb(0.8, 0.45, 0.);
+const PANEL_COLOR: Color = Color::srgba(1., 1., 1., 0.8);
+
+pub struct StatsPlugin;
+
+impl Plugin for StatsPlugin {
+    fn build(&self, app: &mut App) {
+        // Watching a replay isn't playing
+        app.add_systems(Update, record_death.run_if(player_playing))
+            .add_systems(
+                Update,
+                count_flaps.run_if(in_state(GameState::Game).and(player_playing)),
+            )
+            .add_systems(OnEnter(GameState::Stats), stats_setup)
+            .add_systems(OnExit(GameState::Stats), despawn_screen::<OnStatsScreen>)
+            .add_systems(Update, close_stats.run_if(in_state(GameState::Stats)));
+    }
+}
+
+/// Lifetime statistics, kept in the save file.
+#[derive(Resource, Serialize, Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct PlayerStats {
+    pub games_played: u32,
+    pub pipes_passed: u64,
+    pub flaps: u64,
+    pub ground_deaths: u32,
+    pub pipe_deaths: u32,
+    pub longest_survival_secs: f32,
+}
+
+impl PlayerStats {
+    pub fn average_score(&self) -> f32 {
+        if self.games_played == 0 {
+            return 0.;
+        }
+
+        self.pipes_passed as f32 / self.games_played as f32
+    }
+}
+
+#[derive(Component)]
+struct OnStatsScreen;
+
+// Every run ends in exactly one death, so that's where a game gets counted
+fn record_death(
+    mut death_events: EventReader<DeathEvent>,
+    mut stats: ResMut<PlayerStats>,
+    score: Res<Score>,
+    run: Res<CurrentRun>,
+) {
+    for event in death_events.read() {
+        stats.games_played += 1;
+        stats.pipes_passed += **score as u64;
+        stats.longest_survival_secs = stats.longest_survival_secs.max(run.duration.elapsed_secs());
+
+        match event.cause {
+            DeathCause::Ground => stats.ground_deaths += 1,
+            DeathCause::Pipe => stats.pipe_deaths += 1,
+        }
+    }
+}
+
+fn count_flaps(mut jump_events: EventReader<JumpEvent>, mut stats: ResMut<PlayerStats>) {
+    stats.flaps += jump_events.read().count() as u64;
+}
+
+fn stats_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    stats: Res<PlayerStats>,
+    settings: Res<Settings>,
+) {
+    let font = asset_server.load("embedded://flappyboi/../assets/fonts/FiraSans-Bold.ttf");
+    let row_font = TextFont {
+        font: font.clone(),
+        font_size: ROW_FONT_SIZE,
+        ..default()
+    };
+
+    let rows = [
+        ("Games played", stats.games_played.to_string()),
+        ("Pipes passed", stats.pipes_passed.to_string()),
+        ("Average score", format!("{:.1}", stats.average_score())),
+        ("Flaps", stats.flaps.to_string()),
+        ("Hit the ground", stats.ground_deaths.to_string()),
+        ("Hit a pipe", stats.pipe_deaths.to_string()),
+        (
+            "Longest run",
+            format!("{:.1}s", stats.longest_survival_secs),
+        ),
+    ];
+
+    commands
+        .spawn((
+            OnStatsScreen,
+            Node {
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                height: Val::Percent(100.),
+                width: Val::Percent(100.),
+                padding: UiRect::all(Val::Px(10.)),
+                row_gap: Val::Px(8.),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new("Stats"),
+                TextColor(TEXT_COLOR),
+                TextFont {
+                    font: font.clone(),
+                    font_size: TITLE_FONT_SIZE,
+                    ..default()
+                },
+            ));
+
+            parent
+                .spawn((
+                    Node {
+                        flex_direction: FlexDirection::Column,
+                        padding: UiRect::all(Val::Px(8.)),
+                        row_gap: Val::Px(4.),
+                        ..default()
+                    },
+                    BackgroundColor(PANEL_COLOR),
+                ))
+                .with_children(|table| {
+                    for (label, value) in rows {
+                        table
+                            .spawn(Node {
+                                flex_direction: FlexDirection::Row,
+                                ..default()
+                            })
+                            .with_children(|row| {
+                                row.spawn((
+                                    Text::new(label),
+                                    TextColor(TEXT_COLOR),
+                                    row_font.clone(),
+                                    Node {
+                                        width: Val::Px(180.),
+                                        ..default()
+                                    },
+                                ));
+                                row.spawn((
+                                    Text::new(value),
+                                    TextColor(VALUE_COLOR),
+                                    row_font.clone(),
+                                ));
+                            });
+                    }
+                });
+
+            parent.spawn((
+                Text::new(format!(
+                    "Press {} to go back",
+                    settings.bindings.describe(Action::Back)
+                )),
+                TextColor(TEXT_COLOR),
+                TextFont {
+                    font: font.clone(),
+                    font_size: 20.0,
+                    ..default()
+                },
+            ));
+        });
+}
+
+fn close_stats(input: ActionInput, mut game_state: ResMut<NextState<GameState>>) {
+    if input.just_pressed(Action::Back)
+        || input.just_pressed(Action::Confirm)
+        || input.pointer_just_pressed()
+    {
+        game_state.set(GameState::Menu);
+    }
+}
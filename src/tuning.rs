@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::difficulty::{self, CurvePoint, Preset, PresetTuning};
+use bevy::asset::io::file::FileAssetReader;
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, LoadContext};
+use bevy::prelude::*;
+use serde::Deserialize;
+
+// Relative to the assets folder
+const TUNING_PATH: &str = "game.tuning.ron";
+// How often the file on disk is checked for changes
+const WATCH_INTERVAL_SECS: f32 = 0.5;
+
+pub struct TuningPlugin;
+
+impl Plugin for TuningPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(GameTuning::built_in())
+            .init_asset::<GameTuning>()
+            .init_asset_loader::<TuningLoader>()
+            .add_systems(Startup, load_tuning)
+            .add_systems(
+                Update,
+                (
+                    watch_tuning_file,
+                    apply_tuning.run_if(on_event::<AssetEvent<GameTuning>>),
+                )
+                    .run_if(resource_exists::<TuningFile>),
+            );
+    }
+}
+
+/// Physics and pipe tuning, loaded from `assets/game.tuning.ron`. Systems read the resource,
+/// which is swapped out whenever the file is reloaded.
+#[derive(Asset, Resource, TypePath, Deserialize, Clone, Debug)]
+pub struct GameTuning {
+    /// Stored with every replay, so one recorded under different tuning can be told apart.
+    pub version: u32,
+    pub bird: BirdTuning,
+    pub pipes: PipeTuning,
+    pub background_parallax: f32,
+    pub curve: Vec<CurvePoint>,
+    pub presets: BTreeMap<Preset, PresetTuning>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct BirdTuning {
+    pub max_fall_speed: f32,
+    pub size: (f32, f32),
+    pub rise_rotation_speed: f32,
+    pub fall_rotation_speed: f32,
+    pub fall_rotation_threshold: f32,
+    pub max_rise_angle: f32,
+    pub max_fall_angle: f32,
+    pub hover_amplitude: f32,
+    pub hover_speed: f32,
+}
+
+impl BirdTuning {
+    /// Radius of the circle the bird collides with pipes as.
+    pub fn hitbox_radius(&self) -> f32 {
+        self.size.1 / 2. - 1.
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct PipeTuning {
+    pub width: f32,
+    pub height: f32,
+    pub count: usize,
+    pub first_x: f32,
+}
+
+impl GameTuning {
+    fn validate(&self) -> Result<(), String> {
+        difficulty::validate_curve(&self.curve)?;
+        for (preset, tuning) in &self.presets {
+            tuning
+                .validate()
+                .map_err(|e| format!("{:?} preset: {}", preset, e))?;
+        }
+        Ok(())
+    }
+
+    /// The copy of `assets/game.tuning.ron` compiled into the game, used until the file is loaded
+    /// and whenever it can't be.
+    pub fn built_in() -> Self {
+        ron::from_str(include_str!("../assets/game.tuning.ron"))
+            .expect("assets/game.tuning.ron should be valid")
+    }
+}
+
+#[derive(Default)]
+struct TuningLoader;
+
+impl AssetLoader for TuningLoader {
+    type Asset = GameTuning;
+    type Settings = ();
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<GameTuning, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let tuning: GameTuning = ron::de::from_bytes(&bytes)?;
+        // Failing the load keeps the tuning that was in use
+        tuning.validate()?;
+        Ok(tuning)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["tuning.ron"]
+    }
+}
+
+#[derive(Resource)]
+struct TuningFile {
+    handle: Handle<GameTuning>,
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    watch_timer: Timer,
+}
+
+fn load_tuning(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let path = FileAssetReader::get_base_path()
+        .join("assets")
+        .join(TUNING_PATH);
+    // Without the file there is nothing to watch, the built in tuning is all there is
+    if !path.exists() {
+        info!(
+            "No {} next to the game, using the built in tuning",
+            TUNING_PATH
+        );
+        return;
+    }
+
+    commands.insert_resource(TuningFile {
+        handle: asset_server.load(TUNING_PATH),
+        modified: modified_time(&path),
+        path,
+        watch_timer: Timer::from_seconds(WATCH_INTERVAL_SECS, TimerMode::Repeating),
+    });
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+// Checks the modified time by hand, the asset server only watches files with bevy's
+// `file_watcher` feature
+fn watch_tuning_file(
+    mut tuning_file: ResMut<TuningFile>,
+    asset_server: Res<AssetServer>,
+    time: Res<Time>,
+) {
+    if !tuning_file.watch_timer.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    let modified = modified_time(&tuning_file.path);
+    if modified != tuning_file.modified {
+        tuning_file.modified = modified;
+        info!("{} changed, reloading", TUNING_PATH);
+        asset_server.reload(TUNING_PATH);
+    }
+}
+
+fn apply_tuning(
+    mut asset_events: EventReader<AssetEvent<GameTuning>>,
+    tuning_file: Res<TuningFile>,
+    assets: Res<Assets<GameTuning>>,
+    mut tuning: ResMut<GameTuning>,
+) {
+    for event in asset_events.read() {
+        if !event.is_added(&tuning_file.handle) && !event.is_modified(&tuning_file.handle) {
+            continue;
+        }
+        if let Some(loaded) = assets.get(&tuning_file.handle) {
+            info!("Applied {}", TUNING_PATH);
+            *tuning = loaded.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(score: usize, speed: f32, opening: f32, gap: f32) -> CurvePoint {
+        ron::from_str(&format!(
+            "(score: {}, speed: {}, opening: {}, gap: {})",
+            score, speed, opening, gap
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn built_in_tuning_is_valid() {
+        assert_eq!(GameTuning::built_in().validate(), Ok(()));
+    }
+
+    #[test]
+    fn rejects_broken_curves() {
+        let mut tuning = GameTuning::built_in();
+        tuning.curve.clear();
+        assert!(tuning.validate().is_err());
+
+        tuning.curve = vec![point(10, 150., 120., 250.), point(0, 165., 114., 240.)];
+        assert!(tuning.validate().is_err());
+        tuning.curve = vec![point(0, 150., 120., 250.), point(0, 165., 114., 240.)];
+        assert!(tuning.validate().is_err());
+
+        for broken in [
+            point(0, 0., 120., 250.),
+            point(0, 150., -1., 250.),
+            point(0, 150., 120., 0.),
+            point(0, f32::NAN, 120., 250.),
+        ] {
+            tuning.curve = vec![broken];
+            assert!(tuning.validate().is_err(), "{:?}", broken);
+        }
+    }
+}
@@ -0,0 +1,569 @@
+use super::{despawn_screen, GameState, PlayState, Score};
+use crate::game::{CurrentRun, GameMode};
+use crate::input::{key_name, Action, ActionInput, JumpEvent};
+use crate::persistence::SaveRequested;
+use crate::physics::{PhysicsSet, Position};
+use crate::player::{Bird, Dead, DeathEvent, BIRD_X, SCREEN_HEIGHT};
+use crate::settings::Settings;
+use bevy::prelude::*;
+
+pub const MAX_PLAYERS: usize = 4;
+const MIN_PLAYERS: usize = 2;
+/// Far enough apart on the keyboard for four people to share it.
+pub const DEFAULT_KEYS: [KeyCode; MAX_PLAYERS] = [
+    KeyCode::KeyA,
+    KeyCode::KeyL,
+    KeyCode::KeyV,
+    KeyCode::ArrowUp,
+];
+
+/// Every player's bird tint, and a darker shade of it that reads on the sky.
+pub const TINTS: [Color; MAX_PLAYERS] = [
+    Color::srgb(1.0, 0.55, 0.55),
+    Color::srgb(0.55, 0.7, 1.0),
+    Color::srgb(0.6, 1.0, 0.55),
+    Color::srgb(1.0, 0.85, 0.4),
+];
+pub const TEXT_TINTS: [Color; MAX_PLAYERS] = [
+    Color::srgb(0.75, 0.1, 0.1),
+    Color::srgb(0.1, 0.25, 0.8),
+    Color::srgb(0.1, 0.5, 0.1),
+    Color::srgb(0.65, 0.4, 0.),
+];
+
+const TITLE_FONT_SIZE: f32 = 30.0;
+const ROW_FONT_SIZE: f32 = 18.0;
+const HUD_FONT_SIZE: f32 = 18.0;
+const TEXT_COLOR: Color = Color::srgb(0.1, 0., 0.);
+const PANEL_COLOR: Color = Color::srgba(1., 1., 1., 0.8);
+const SELECTED_ROW_COLOR: Color = Color::srgb(1.0, 0.7, 0.1);
+const LISTENING_ROW_COLOR: Color = Color::srgb(1.0, 0.45, 0.3);
+
+// The player count, one row per player's key and the start row
+const ROW_COUNT: usize = MAX_PLAYERS + 2;
+// A flap key that also does one of these would pause or leave the round
+const RESERVED_ACTIONS: [Action; 3] = [Action::Pause, Action::Back, Action::Quit];
+
+/// Local multiplayer: a lobby to pick the players and their keys, a bird for each of them
+/// on the same pipes, and the standings once the last one is down.
+pub struct VersusPlugin;
+
+impl Plugin for VersusPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LobbySelection>()
+            .init_resource::<Standings>()
+            .add_systems(OnEnter(GameState::VersusLobby), lobby_setup)
+            .add_systems(
+                OnExit(GameState::VersusLobby),
+                (despawn_screen::<OnLobbyScreen>, save_lobby),
+            )
+            .add_systems(
+                Update,
+                (
+                    lobby_input,
+                    update_lobby_rows.run_if(resource_changed::<LobbySelection>),
+                )
+                    .chain()
+                    .run_if(in_state(GameState::VersusLobby)),
+            )
+            .add_systems(
+                OnEnter(GameState::Game),
+                (spawn_contenders, hud_setup).run_if(resource_equals(GameMode::Versus)),
+            )
+            .add_systems(OnExit(GameState::Game), despawn_screen::<OnVersusHud>)
+            // Flaps are in before `Update`, like the player's
+            .add_systems(
+                PreUpdate,
+                flap_contenders.after(bevy::input::InputSystem).run_if(
+                    in_state(PlayState::GetReady)
+                        .or(in_state(PlayState::Running))
+                        .and(resource_equals(GameMode::Versus)),
+                ),
+            )
+            // Right after the crash, before another tick can pass a pipe
+            .add_systems(
+                FixedUpdate,
+                record_finishes
+                    .after(PhysicsSet::Collide)
+                    .run_if(on_event::<DeathEvent>.and(resource_equals(GameMode::Versus))),
+            )
+            .add_systems(
+                Update,
+                update_hud.run_if(in_state(GameState::Game).and(resource_equals(GameMode::Versus))),
+            )
+            .add_systems(
+                OnEnter(GameState::DeathScreen),
+                results_setup.run_if(resource_equals(GameMode::Versus)),
+            )
+            .add_systems(
+                OnExit(GameState::DeathScreen),
+                despawn_screen::<OnResultsPanel>,
+            );
+    }
+}
+
+/// The bird of one versus player, by player index.
+#[derive(Component)]
+pub struct Contender(usize);
+
+/// How every player that crashed this round did, in the order they went down.
+#[derive(Resource, Default)]
+struct Standings(Vec<Finish>);
+
+struct Finish {
+    player: usize,
+    score: usize,
+    /// Ticks into the round, what breaks ties between equal scores.
+    ticks: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LobbyRow {
+    Players,
+    Key(usize),
+    Start,
+}
+
+/// The lobby's picks, saved in the settings on the way out.
+#[derive(Resource)]
+struct LobbySelection {
+    index: usize,
+    /// Waiting for the key to give to the selected player.
+    listening: bool,
+    /// The last key turned down while listening, because it's needed for something else.
+    refused: Option<KeyCode>,
+    players: usize,
+    keys: [KeyCode; MAX_PLAYERS],
+}
+
+impl Default for LobbySelection {
+    fn default() -> Self {
+        Self {
+            index: 0,
+            listening: false,
+            refused: None,
+            players: MIN_PLAYERS,
+            keys: DEFAULT_KEYS,
+        }
+    }
+}
+
+#[derive(Component)]
+struct OnLobbyScreen;
+
+#[derive(Component)]
+struct LobbyRowText(usize);
+
+#[derive(Component)]
+struct LobbyHint;
+
+#[derive(Component)]
+struct OnVersusHud;
+
+#[derive(Component)]
+struct ContenderScore(usize);
+
+#[derive(Component)]
+struct OnResultsPanel;
+
+fn player_count(settings: &Settings) -> usize {
+    settings.versus_players.clamp(MIN_PLAYERS, MAX_PLAYERS)
+}
+
+fn reserved(settings: &Settings, key: KeyCode) -> bool {
+    RESERVED_ACTIONS
+        .iter()
+        .any(|&action| settings.bindings.keys(action).contains(&key))
+}
+
+fn lobby_rows(players: usize) -> Vec<LobbyRow> {
+    std::iter::once(LobbyRow::Players)
+        .chain((0..players).map(LobbyRow::Key))
+        .chain(std::iter::once(LobbyRow::Start))
+        .collect()
+}
+
+fn lobby_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut selection: ResMut<LobbySelection>,
+    settings: Res<Settings>,
+) {
+    *selection = LobbySelection {
+        players: player_count(&settings),
+        keys: settings.versus_keys,
+        ..default()
+    };
+    let font = asset_server.load("embedded://flappyboi/../assets/fonts/FiraSans-Bold.ttf");
+    let row_font = TextFont {
+        font: font.clone(),
+        font_size: ROW_FONT_SIZE,
+        ..default()
+    };
+
+    commands
+        .spawn((
+            OnLobbyScreen,
+            Node {
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                height: Val::Percent(100.),
+                width: Val::Percent(100.),
+                padding: UiRect::all(Val::Px(10.)),
+                row_gap: Val::Px(6.),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Text::new("Versus"),
+                TextColor(TEXT_COLOR),
+                TextFont {
+                    font: font.clone(),
+                    font_size: TITLE_FONT_SIZE,
+                    ..default()
+                },
+            ));
+
+            parent
+                .spawn((
+                    Node {
+                        flex_direction: FlexDirection::Column,
+                        padding: UiRect::all(Val::Px(6.)),
+                        row_gap: Val::Px(2.),
+                        ..default()
+                    },
+                    BackgroundColor(PANEL_COLOR),
+                ))
+                .with_children(|table| {
+                    // Rows past the current player count stay hidden
+                    for index in 0..ROW_COUNT {
+                        table.spawn((
+                            LobbyRowText(index),
+                            Text::default(),
+                            TextColor(TEXT_COLOR),
+                            row_font.clone(),
+                            Node {
+                                width: Val::Px(240.),
+                                padding: UiRect::axes(Val::Px(4.), Val::Px(2.)),
+                                ..default()
+                            },
+                            BackgroundColor(Color::NONE),
+                        ));
+                    }
+                });
+
+            parent.spawn((LobbyHint, Text::default(), TextColor(TEXT_COLOR), row_font));
+        });
+}
+
+fn lobby_input(
+    input: ActionInput,
+    keys: Res<ButtonInput<KeyCode>>,
+    settings: Res<Settings>,
+    mut selection: ResMut<LobbySelection>,
+    mut mode: ResMut<GameMode>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    let rows = lobby_rows(selection.players);
+    if selection.listening {
+        if input.just_pressed(Action::Back) {
+            selection.listening = false;
+            selection.refused = None;
+            return;
+        }
+        let (Some(&key), Some(&LobbyRow::Key(player))) =
+            (keys.get_just_pressed().next(), rows.get(selection.index))
+        else {
+            return;
+        };
+        if reserved(&settings, key) {
+            selection.refused = Some(key);
+            return;
+        }
+        // Taking someone else's key hands them the old one, so no two players share
+        let keys = &mut selection.keys;
+        match keys.iter().position(|&bound| bound == key) {
+            Some(other) => keys.swap(other, player),
+            None => keys[player] = key,
+        }
+        selection.listening = false;
+        selection.refused = None;
+        return;
+    }
+
+    if input.just_pressed(Action::Down) {
+        selection.index = (selection.index + 1) % rows.len();
+    } else if input.just_pressed(Action::Up) {
+        selection.index = (selection.index + rows.len() - 1) % rows.len();
+    } else if input.just_pressed(Action::Confirm) {
+        match rows[selection.index] {
+            LobbyRow::Players => {
+                selection.players = (selection.players % MAX_PLAYERS + 1).max(MIN_PLAYERS);
+            }
+            LobbyRow::Key(_) => selection.listening = true,
+            LobbyRow::Start => {
+                *mode = GameMode::Versus;
+                game_state.set(GameState::Game);
+            }
+        }
+    } else if input.just_pressed(Action::Back) {
+        game_state.set(GameState::Menu);
+    }
+}
+
+fn update_lobby_rows(
+    mut selection: ResMut<LobbySelection>,
+    settings: Res<Settings>,
+    mut row_q: Query<
+        (&LobbyRowText, &mut Text, &mut Node, &mut BackgroundColor),
+        Without<LobbyHint>,
+    >,
+    mut hint_q: Query<&mut Text, With<LobbyHint>>,
+) {
+    let rows = lobby_rows(selection.players);
+    // The start row moves up when players are taken away
+    if selection.index >= rows.len() {
+        selection.index = rows.len() - 1;
+    }
+
+    for (row, mut text, mut node, mut background) in row_q.iter_mut() {
+        let Some(&lobby_row) = rows.get(row.0) else {
+            node.display = Display::None;
+            continue;
+        };
+        node.display = Display::Flex;
+        text.0 = match lobby_row {
+            LobbyRow::Players => format!("Players: {}", rows.len() - 2),
+            LobbyRow::Key(player) => format!(
+                "Player {} flaps with {}",
+                player + 1,
+                key_name(selection.keys[player])
+            ),
+            LobbyRow::Start => "Start".to_string(),
+        };
+
+        background.0 = match (row.0 == selection.index, selection.listening) {
+            (true, true) => LISTENING_ROW_COLOR,
+            (true, false) => SELECTED_ROW_COLOR,
+            (false, _) => match lobby_row {
+                LobbyRow::Key(player) => TINTS[player],
+                _ => Color::NONE,
+            },
+        };
+    }
+
+    for mut hint in hint_q.iter_mut() {
+        hint.0 = match (selection.listening, selection.refused) {
+            (true, Some(key)) => format!("{} is taken, try another", key_name(key)),
+            (true, None) => format!(
+                "Press the key for this player, {} to cancel",
+                settings.bindings.describe(Action::Back)
+            ),
+            (false, _) => format!(
+                "{} to change, {} to go back",
+                settings.bindings.describe(Action::Confirm),
+                settings.bindings.describe(Action::Back)
+            ),
+        };
+    }
+}
+
+fn save_lobby(
+    selection: Res<LobbySelection>,
+    mut settings: ResMut<Settings>,
+    mut save_requests: EventWriter<SaveRequested>,
+) {
+    settings.versus_players = selection.players;
+    settings.versus_keys = selection.keys;
+    save_requests.send_default();
+}
+
+// Same spot for everyone, the tints tell them apart
+fn spawn_contenders(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    mut standings: ResMut<Standings>,
+) {
+    standings.0.clear();
+    for (player, &color) in TINTS.iter().enumerate().take(player_count(&settings)) {
+        commands.spawn((
+            Bird::default(),
+            Contender(player),
+            Sprite { color, ..default() },
+            Position::new(Vec2::new(BIRD_X, SCREEN_HEIGHT / 2.)),
+            Transform::from_xyz(BIRD_X, SCREEN_HEIGHT / 2., 5. + player as f32 * 0.1),
+        ));
+    }
+}
+
+fn hud_setup(mut commands: Commands, asset_server: Res<AssetServer>, settings: Res<Settings>) {
+    let font = asset_server.load("embedded://flappyboi/../assets/fonts/FiraSans-Bold.ttf");
+    commands
+        .spawn((
+            OnVersusHud,
+            Node {
+                position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::End,
+                top: Val::Px(5.),
+                right: Val::Px(5.),
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            for (player, &color) in TEXT_TINTS.iter().enumerate().take(player_count(&settings)) {
+                parent.spawn((
+                    ContenderScore(player),
+                    Text::default(),
+                    TextColor(color),
+                    TextFont {
+                        font: font.clone(),
+                        font_size: HUD_FONT_SIZE,
+                        ..default()
+                    },
+                ));
+            }
+        });
+}
+
+// The first flap sends every bird off together, so nobody starts behind
+fn flap_contenders(
+    keys: Res<ButtonInput<KeyCode>>,
+    settings: Res<Settings>,
+    play_state: Res<State<PlayState>>,
+    mut bird_q: Query<(&Contender, &mut Bird), Without<Dead>>,
+    mut jump_events: EventWriter<JumpEvent>,
+) {
+    let flapped = |player: usize| keys.just_pressed(settings.versus_keys[player]);
+    if !bird_q.iter().any(|(contender, _)| flapped(contender.0)) {
+        return;
+    }
+
+    let everyone = *play_state == PlayState::GetReady;
+    for (contender, mut bird) in bird_q.iter_mut() {
+        if everyone || flapped(contender.0) {
+            bird.flap();
+        }
+    }
+    // There's no player bird for it to flap, it starts the run and plays the flap sound
+    jump_events.send_default();
+}
+
+// Birds that crash while others fly on are hidden, they'd hang in the air otherwise
+fn record_finishes(
+    mut death_events: EventReader<DeathEvent>,
+    score: Res<Score>,
+    run: Res<CurrentRun>,
+    mut standings: ResMut<Standings>,
+    mut contender_q: Query<(&Contender, &mut Visibility)>,
+    alive_q: Query<(), (With<Contender>, Without<Dead>)>,
+) {
+    for event in death_events.read() {
+        let Ok((contender, mut visibility)) = contender_q.get_mut(event.bird) else {
+            continue;
+        };
+        standings.0.push(Finish {
+            player: contender.0,
+            score: **score,
+            ticks: run.ticks,
+        });
+        if !alive_q.is_empty() {
+            *visibility = Visibility::Hidden;
+        }
+    }
+}
+
+fn update_hud(
+    score: Res<Score>,
+    settings: Res<Settings>,
+    standings: Res<Standings>,
+    mut text_q: Query<(&ContenderScore, &mut Text)>,
+) {
+    for (contender, mut text) in text_q.iter_mut() {
+        let player = contender.0;
+        let label = format!(
+            "P{} ({})",
+            player + 1,
+            key_name(settings.versus_keys[player])
+        );
+        text.0 = match standings.0.iter().find(|finish| finish.player == player) {
+            Some(finish) => format!("{}: {} out", label, finish.score),
+            None => format!("{}: {}", label, **score),
+        };
+    }
+}
+
+fn results_setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    standings: Res<Standings>,
+) {
+    let font = asset_server.load("embedded://flappyboi/../assets/fonts/FiraSans-Bold.ttf");
+    let row_font = TextFont {
+        font: font.clone(),
+        font_size: ROW_FONT_SIZE,
+        ..default()
+    };
+
+    let mut ranked: Vec<&Finish> = standings.0.iter().collect();
+    ranked.sort_by_key(|finish| std::cmp::Reverse((finish.score, finish.ticks)));
+
+    commands
+        .spawn((
+            OnResultsPanel,
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.),
+                top: Val::Px(110.),
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn((
+                    Node {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        padding: UiRect::all(Val::Px(10.)),
+                        row_gap: Val::Px(4.),
+                        ..default()
+                    },
+                    BackgroundColor(PANEL_COLOR),
+                    BorderRadius::all(Val::Px(8.)),
+                ))
+                .with_children(|panel| {
+                    panel.spawn((
+                        Text::new("Results"),
+                        TextColor(TEXT_COLOR),
+                        TextFont {
+                            font: font.clone(),
+                            font_size: TITLE_FONT_SIZE,
+                            ..default()
+                        },
+                    ));
+
+                    // Players that went down on the same tick share their place
+                    let mut place = 0;
+                    for (index, finish) in ranked.iter().enumerate() {
+                        let tied = index > 0
+                            && (ranked[index - 1].score, ranked[index - 1].ticks)
+                                == (finish.score, finish.ticks);
+                        if !tied {
+                            place = index + 1;
+                        }
+                        panel.spawn((
+                            Text::new(format!(
+                                "{}. Player {}: {}",
+                                place,
+                                finish.player + 1,
+                                finish.score
+                            )),
+                            TextColor(TEXT_TINTS[finish.player]),
+                            row_font.clone(),
+                        ));
+                    }
+                });
+        });
+}